@@ -0,0 +1,103 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use morr::line_reader::{LineReader, LinesRange};
+
+const SIZE: usize = 100 << 20;
+const ROWS: usize = 50;
+
+// Lines of 0 to 199 bytes, lengths picked by a fixed LCG so runs compare.
+fn synthetic(size: usize) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(size + 200);
+    let mut seed: u32 = 1;
+    while buf.len() < size {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        let len = (seed >> 16) as usize % 200;
+        buf.extend(std::iter::repeat_n(b'x', len));
+        buf.push(b'\n');
+    }
+    buf
+}
+
+fn bench(c: &mut Criterion) {
+    let buf = synthetic(SIZE);
+
+    c.bench_function("top", |b| {
+        b.iter(|| {
+            let mut reader = LineReader::new(&buf, "");
+            black_box(reader.read(&LinesRange::pos(0..ROWS)).lines.len())
+        })
+    });
+
+    c.bench_function("bottom", |b| {
+        b.iter(|| {
+            let mut reader = LineReader::new(&buf, "");
+            black_box(reader.read(&LinesRange::neg(0..ROWS)).lines.len())
+        })
+    });
+
+    c.bench_function("full scan", |b| {
+        b.iter(|| {
+            let mut reader = LineReader::new(&buf, "");
+            black_box(reader.total_lines())
+        })
+    });
+
+    c.bench_function("line by line", |b| {
+        let mut reader = LineReader::new(&buf, "");
+        let mut top = 0;
+        b.iter(|| {
+            top += 1;
+            black_box(
+                reader.read(&LinesRange::pos(top..top + ROWS)).lines.len(),
+            )
+        })
+    });
+
+    // Only the page move is timed; the read-ahead stands in for what the
+    // pager does while waiting for the key.
+    for (name, read_ahead) in
+        [("page down", false), ("page down after read-ahead", true)]
+    {
+        c.bench_function(name, |b| {
+            let mut reader = LineReader::new(&buf, "");
+            let mut top = 0;
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let view = LinesRange::pos(top..top + ROWS);
+                    if read_ahead {
+                        reader.read(&view.extendl(ROWS).extendr(ROWS));
+                    }
+                    top += ROWS;
+                    let start = Instant::now();
+                    let next = LinesRange::pos(top..top + ROWS);
+                    black_box(reader.read(&next).lines.len());
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+    }
+
+    c.bench_function("random seek", |b| {
+        let mut reader = LineReader::new(&buf, "");
+        let total = reader.total_lines();
+        let mut seed: u64 = 1;
+        b.iter(|| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            let top = (seed >> 33) as usize % total;
+            black_box(
+                reader.read(&LinesRange::pos(top..top + ROWS)).lines.len(),
+            )
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench
+}
+criterion_main!(benches);
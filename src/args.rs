@@ -0,0 +1,206 @@
+use crate::options::Theme;
+use encoding_rs::{Encoding, UTF_8};
+use crate::palette::ColorMode;
+use std::ops::Range;
+
+pub const USAGE: &str =
+    "usage: morr [-e|--end|+G] [-F|--quit-if-one-screen]
+            [-r|--raw-control-chars] [-b|--number-nonblank]
+            [--group-continuations[=PREFIX]] [-X|--no-alternate-screen]
+            [--light|--dark] [--scrollbar] [--pattern-file=FILE]
+            [--filter=COMMAND] [--hyperlinks] [--filler=MARKER]
+            [--color=always|auto|never] [--charset=NAME] [--scroll=N]
+            [--scrolloff=N] [--hscroll=N] [--lines=START:END]
+            [--bytes=START:END] [--no-history] [--squeeze-repeated-lines]
+            [--max-forward-scan=LINES] [--idle-index] FILE";
+
+pub struct Args {
+    pub filename: String,
+    pub end: bool,
+    pub filler: String,
+    pub quit_if_one_screen: bool,
+    pub raw: bool,
+    pub scrollbar: bool,
+    pub hyperlinks: bool,
+    pub no_alternate_screen: bool,
+    pub theme: Theme,
+    pub pattern_file: Option<String>,
+    pub filter: Option<String>,
+    pub lines: Option<Range<usize>>,
+    pub bytes: Option<Range<usize>>,
+    pub scroll: usize,
+    pub scrolloff: usize,
+    pub hscroll: usize,
+    pub number_nonblank: bool,
+    pub color: ColorMode,
+    pub charset: &'static Encoding,
+    pub group: Option<String>,
+    pub no_history: bool,
+    pub squeeze: bool,
+    pub max_forward_scan: usize,
+    pub idle_index: bool,
+}
+
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut filename = None;
+    let mut end = false;
+    let mut filler = String::from("~");
+    let mut quit_if_one_screen = false;
+    let mut raw = false;
+    let mut scrollbar = false;
+    let mut hyperlinks = false;
+    let mut no_alternate_screen = false;
+    let mut theme = Theme::Terminal;
+    let mut pattern_file = None;
+    let mut filter = None;
+    let mut lines = None;
+    let mut bytes = None;
+    let mut scroll = 1;
+    let mut scrolloff = 0;
+    let mut hscroll = 0;
+    let mut number_nonblank = false;
+    let mut color = ColorMode::Auto;
+    let mut charset = UTF_8;
+    let mut group = None;
+    let mut no_history = false;
+    let mut squeeze = false;
+    let mut max_forward_scan = 1 << 20;
+    let mut idle_index = false;
+    for arg in args {
+        match arg.as_str() {
+            "-e" | "--end" | "+G" => end = true,
+            "-F" | "--quit-if-one-screen" => quit_if_one_screen = true,
+            "-r" | "--raw-control-chars" => raw = true,
+            "-b" | "--number-nonblank" => number_nonblank = true,
+            "--scrollbar" => scrollbar = true,
+            "--no-history" => no_history = true,
+            "--idle-index" => idle_index = true,
+            "--squeeze-repeated-lines" => squeeze = true,
+            "--group-continuations" => group = Some(String::new()),
+            "--hyperlinks" => hyperlinks = true,
+            "-X" | "--no-alternate-screen" => no_alternate_screen = true,
+            "--light" => theme = Theme::Light,
+            "--dark" => theme = Theme::Dark,
+            opt if opt.starts_with("--pattern-file=") => {
+                pattern_file = Some(opt["--pattern-file=".len()..].to_owned())
+            }
+            opt if opt.starts_with("--filter=") => {
+                filter = Some(opt["--filter=".len()..].to_owned())
+            }
+            opt if opt.starts_with("--lines=") => {
+                lines = Some(line_range(&opt["--lines=".len()..])?)
+            }
+            opt if opt.starts_with("--color=") => {
+                color = match &opt["--color=".len()..] {
+                    "always" => ColorMode::Always,
+                    "auto" => ColorMode::Auto,
+                    "never" => ColorMode::Never,
+                    _ => return Err(format!("invalid value: {}", opt)),
+                }
+            }
+            opt if opt.starts_with("--group-continuations=") => {
+                group = Some(opt["--group-continuations=".len()..].to_owned())
+            }
+            // Any that leaves ASCII alone, since lines are split on the
+            // newline byte before they're decoded.
+            opt if opt.starts_with("--charset=") => {
+                let name = &opt["--charset=".len()..];
+                charset = Encoding::for_label(name.as_bytes())
+                    .filter(|charset| charset.is_ascii_compatible())
+                    .ok_or_else(|| format!("unsupported charset: {}", name))?
+            }
+            opt if opt.starts_with("--max-forward-scan=") => {
+                max_forward_scan = opt["--max-forward-scan=".len()..]
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid value: {}", opt))?
+            }
+            opt if opt.starts_with("--scroll=") => {
+                scroll = opt["--scroll=".len()..]
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid value: {}", opt))?
+            }
+            opt if opt.starts_with("--scrolloff=") => {
+                scrolloff = opt["--scrolloff=".len()..]
+                    .parse()
+                    .map_err(|_| format!("invalid value: {}", opt))?
+            }
+            opt if opt.starts_with("--hscroll=") => {
+                hscroll = opt["--hscroll=".len()..]
+                    .parse()
+                    .map_err(|_| format!("invalid value: {}", opt))?
+            }
+            opt if opt.starts_with("--bytes=") => {
+                bytes = Some(byte_range(&opt["--bytes=".len()..])?)
+            }
+            opt if opt.starts_with("--filler=") => {
+                filler = opt["--filler=".len()..].to_owned()
+            }
+            opt if opt.starts_with('-') && opt.len() > 1 => {
+                return Err(format!("unknown option: {}", opt))
+            }
+            _ if filename.is_some() => {
+                return Err(format!("unexpected argument: {}", arg))
+            }
+            _ => filename = Some(arg),
+        }
+    }
+    Ok(Args {
+        filename: filename.ok_or("no file name passed")?,
+        end,
+        filler,
+        quit_if_one_screen,
+        raw,
+        scrollbar,
+        hyperlinks,
+        no_alternate_screen,
+        theme,
+        pattern_file,
+        filter,
+        lines,
+        bytes,
+        scroll,
+        scrolloff,
+        hscroll,
+        number_nonblank,
+        color,
+        charset,
+        group,
+        no_history,
+        squeeze,
+        max_forward_scan,
+        idle_index,
+    })
+}
+
+// Parses "START:END", 1-based and inclusive like `sed -n START,ENDp`, into
+// the 0-based range the line reader takes.
+fn line_range(arg: &str) -> Result<Range<usize>, String> {
+    let invalid = || format!("invalid line range: {}", arg);
+    let (start, end) = arg.split_once(':').ok_or_else(invalid)?;
+    let start: usize = start.parse().map_err(|_| invalid())?;
+    let end: usize = end.parse().map_err(|_| invalid())?;
+    if start == 0 || end < start {
+        return Err(invalid());
+    }
+    Ok(start - 1..end)
+}
+
+// Parses "START:END" as byte offsets from 0, END not included. Either may
+// be left out to mean the start or the end of the file.
+fn byte_range(arg: &str) -> Result<Range<usize>, String> {
+    let invalid = || format!("invalid byte range: {}", arg);
+    let (start, end) = arg.split_once(':').ok_or_else(invalid)?;
+    let offset = |s: &str, default| match s {
+        "" => Ok(default),
+        s => s.parse().map_err(|_| invalid()),
+    };
+    let range = offset(start, 0)?..offset(end, usize::MAX)?;
+    if range.is_empty() {
+        return Err(invalid());
+    }
+    Ok(range)
+}
@@ -0,0 +1,44 @@
+use std::fs::File;
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+fn spawn(cmd: &str, stdin: Stdio) -> Result<Child, String> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("can't run {}: {}", cmd, e))
+}
+
+fn output(cmd: &str, child: Child) -> Result<Vec<u8>, String> {
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("{}: {}", cmd, e))?;
+    if !output.status.success() {
+        return Err(format!("{}: {}", cmd, output.status));
+    }
+    if output.stdout.is_empty() {
+        return Err(format!("{}: no output", cmd));
+    }
+    Ok(output.stdout)
+}
+
+pub fn file(cmd: &str, path: &str) -> Result<Vec<u8>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    output(cmd, spawn(cmd, Stdio::from(file))?)
+}
+
+// Feeds the input from another thread so a command that writes before it
+// has read everything can't block on a full pipe.
+pub fn text(cmd: &str, input: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut child = spawn(cmd, Stdio::piped())?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = output(cmd, child);
+    let _ = writer.join();
+    output
+}
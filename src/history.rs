@@ -0,0 +1,61 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const MAX_ENTRIES: usize = 100;
+
+// Where the last position in each file is kept: $MORR_HISTFILE, or
+// ~/.morr_history.
+fn location() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("MORR_HISTFILE") {
+        return Some(path.into());
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".morr_history"))
+}
+
+// Only regular files are remembered, by their canonical path.
+pub fn key(filename: &str) -> Option<String> {
+    let path = fs::canonicalize(filename).ok()?;
+    if !path.is_file() {
+        return None;
+    }
+    path.to_str()
+        .filter(|path| !path.contains('\n'))
+        .map(String::from)
+}
+
+// "LINE\tPATH" lines, most recently closed first.
+fn load() -> Vec<(usize, String)> {
+    let text = location()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    text.lines()
+        .filter_map(|entry| {
+            let (line, path) = entry.split_once('\t')?;
+            Some((line.parse().ok()?, path.to_owned()))
+        })
+        .collect()
+}
+
+pub fn position(key: &str) -> Option<usize> {
+    load()
+        .into_iter()
+        .find(|(_, path)| path == key)
+        .map(|(line, _)| line)
+}
+
+// Puts the file first and drops the entries that were closed longest ago.
+// Failing to write the history isn't worth bothering anyone about.
+pub fn save(key: &str, line: usize) {
+    let mut entries = load();
+    entries.retain(|(_, path)| path != key);
+    entries.insert(0, (line, key.to_owned()));
+    entries.truncate(MAX_ENTRIES);
+    let text: String = entries
+        .iter()
+        .map(|(line, path)| format!("{}\t{}\n", line, path))
+        .collect();
+    if let Some(path) = location() {
+        let _ = fs::write(path, text);
+    }
+}
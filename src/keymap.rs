@@ -0,0 +1,393 @@
+use crossterm::event::KeyCode::{
+    self, Char, Down, Esc, Left, PageDown, PageUp, Right, Up,
+};
+use crossterm::event::{Event, KeyEvent, KeyModifiers};
+
+use crate::{Anchor, Command, HorizontalMove, SaveScope, VerticalMove};
+
+#[derive(Clone, Copy)]
+struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    fn matches(&self, key: &KeyEvent) -> bool {
+        self.code == key.code
+            && self.modifiers == key.modifiers - KeyModifiers::SHIFT
+    }
+
+    fn name(&self) -> String {
+        match self.code {
+            Char(c) if self.modifiers == KeyModifiers::CONTROL => {
+                format!("^{}", c.to_ascii_uppercase())
+            }
+            Char(c) => c.to_string(),
+            code => format!("{:?}", code),
+        }
+    }
+}
+
+const fn key(code: KeyCode) -> Key {
+    Key {
+        code,
+        modifiers: KeyModifiers::NONE,
+    }
+}
+
+const fn ctrl(c: char) -> Key {
+    Key {
+        code: Char(c),
+        modifiers: KeyModifiers::CONTROL,
+    }
+}
+
+struct Binding {
+    keys: &'static [Key],
+    command: Command,
+}
+
+impl Binding {
+    fn matches(&self, keys: &[KeyEvent]) -> bool {
+        self.keys.len() == keys.len() && self.starts_with(keys)
+    }
+
+    fn starts_with(&self, keys: &[KeyEvent]) -> bool {
+        self.keys.len() >= keys.len()
+            && self.keys.iter().zip(keys).all(|(k, evt)| k.matches(evt))
+    }
+
+    fn name(&self) -> String {
+        self.keys.iter().map(Key::name).collect()
+    }
+}
+
+const fn bind(keys: &'static [Key], command: Command) -> Binding {
+    Binding { keys, command }
+}
+
+const KEYMAP: &[Binding] = &[
+    bind(&[key(Char('q'))], Command::Quit),
+    bind(&[key(Char('j'))], Command::V(VerticalMove::LineDown)),
+    bind(&[key(Down)], Command::V(VerticalMove::LineDown)),
+    bind(&[key(Char('k'))], Command::V(VerticalMove::LineUp)),
+    bind(&[key(Up)], Command::V(VerticalMove::LineUp)),
+    bind(&[ctrl('d')], Command::V(VerticalMove::HalfPageDown)),
+    bind(&[ctrl('u')], Command::V(VerticalMove::HalfPageUp)),
+    bind(&[key(PageDown)], Command::V(VerticalMove::PageDown)),
+    bind(&[key(PageUp)], Command::V(VerticalMove::PageUp)),
+    bind(&[key(Char('}'))], Command::V(VerticalMove::ParagraphDown)),
+    bind(&[key(Char('{'))], Command::V(VerticalMove::ParagraphUp)),
+    bind(&[key(Char('G'))], Command::V(VerticalMove::Bottom)),
+    bind(&[key(Char('g'))], Command::V(VerticalMove::Top)),
+    bind(&[key(Left)], Command::H(HorizontalMove::Left)),
+    bind(&[key(Right)], Command::H(HorizontalMove::Right)),
+    bind(
+        &[key(Char('z')), key(Char('t'))],
+        Command::Reposition(Anchor::Top),
+    ),
+    bind(
+        &[key(Char('z')), key(Char('z'))],
+        Command::Reposition(Anchor::Center),
+    ),
+    bind(
+        &[key(Char('z')), key(Char('b'))],
+        Command::Reposition(Anchor::Bottom),
+    ),
+    bind(&[key(Char(':'))], Command::Execute),
+    bind(&[key(Char('|'))], Command::Filter),
+    bind(&[key(Char('!'))], Command::PipeLine),
+    bind(&[key(Char('T'))], Command::GotoTime),
+    bind(&[key(Char('/'))], Command::Search),
+    bind(&[key(Char('.'))], Command::Repeat),
+    bind(&[key(Char('%'))], Command::MatchBracket),
+    bind(&[key(Char('x'))], Command::HexDump),
+    bind(&[key(Esc)], Command::ClearHighlight),
+    bind(&[key(Char('n'))], Command::SearchNext),
+    bind(&[key(Char('N'))], Command::SearchPrev),
+    bind(&[key(Char('*'))], Command::SearchWord),
+    bind(&[key(Char('#'))], Command::SearchWordBack),
+    bind(&[key(Char('s'))], Command::Save(SaveScope::Screen)),
+    bind(&[key(Char('S'))], Command::Save(SaveScope::File)),
+    bind(&[key(Char('R'))], Command::Ruler),
+    bind(&[key(Char('B'))], Command::ToggleScrollbar),
+    bind(&[key(Char('C'))], Command::ToggleCenter),
+    bind(&[key(Char('M'))], Command::ToggleMinimap),
+    bind(&[key(Char('i'))], Command::ToggleTheme),
+    bind(&[key(Char('l'))], Command::ToggleLineNumbers),
+    bind(&[key(Char('L'))], Command::ToggleRelativeNumbers),
+    bind(&[key(Char('?'))], Command::Help),
+    bind(&[key(Char('h'))], Command::Help),
+];
+
+impl Command {
+    // Commands that `.` repeats: moves and jumps, not toggles or prompts.
+    pub fn repeatable(&self) -> bool {
+        matches!(
+            self,
+            Command::V(_)
+                | Command::H(_)
+                | Command::Reposition(_)
+                | Command::MatchBracket
+                | Command::SearchNext
+                | Command::SearchPrev
+                | Command::SearchWord
+                | Command::SearchWordBack
+        )
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Command::Quit => "Quit",
+            Command::Help => "Show this help",
+            Command::Execute => {
+                "Run :set or :range, or find any command by name"
+            }
+            Command::Filter => "Show the screen piped through a command",
+            Command::PipeLine => {
+                "Show the current line piped through a command"
+            }
+            Command::GotoTime => "Go to the first line at or after a time",
+            Command::Search => "Search forward as you type",
+            Command::Repeat => "Repeat the last move or jump",
+            Command::MatchBracket => "Jump to the matching bracket",
+            Command::HexDump => "Show the bytes of the current line in hex",
+            Command::ClearHighlight => {
+                "Hide search highlighting until the next search"
+            }
+            Command::SearchNext => "Go to the next match",
+            Command::SearchPrev => "Go to the previous match",
+            Command::SearchWord => "Search forward for the current word",
+            Command::SearchWordBack => "Search backward for the current word",
+            Command::Save(SaveScope::Screen) => "Save the screen to a file",
+            Command::Save(SaveScope::File) => "Save the whole file to a file",
+            Command::Ruler => "Show a column ruler and the screen width",
+            Command::ToggleScrollbar => "Toggle the scrollbar",
+            Command::ToggleCenter => "Toggle centering the lines",
+            Command::ToggleMinimap => {
+                "Toggle an overview of line lengths and matches"
+            }
+            Command::ToggleTheme => "Switch between dark and light colors",
+            Command::ToggleLineNumbers => "Toggle line numbers",
+            Command::ToggleRelativeNumbers => "Toggle relative line numbers",
+            Command::V(VerticalMove::Bottom) => "Go to the end of the file",
+            Command::V(VerticalMove::HalfPageDown) => "Scroll half a page down",
+            Command::V(VerticalMove::HalfPageUp) => "Scroll half a page up",
+            Command::V(VerticalMove::LineDown) => "Scroll one line down",
+            Command::V(VerticalMove::LineUp) => "Scroll one line up",
+            Command::V(VerticalMove::PageDown) => "Scroll one page down",
+            Command::V(VerticalMove::PageUp) => "Scroll one page up",
+            Command::V(VerticalMove::ParagraphDown) => {
+                "Go to the next blank line"
+            }
+            Command::V(VerticalMove::ParagraphUp) => {
+                "Go to the previous blank line"
+            }
+            Command::V(VerticalMove::Top) => "Go to the beginning of the file",
+            Command::H(HorizontalMove::Left) => "Scroll left",
+            Command::H(HorizontalMove::Right) => "Scroll right",
+            Command::Reposition(Anchor::Top) => {
+                "Move the current line to the top of the screen"
+            }
+            Command::Reposition(Anchor::Center) => {
+                "Move the current line to the center of the screen"
+            }
+            Command::Reposition(Anchor::Bottom) => {
+                "Move the current line to the bottom of the screen"
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Parser {
+    pending: Vec<KeyEvent>,
+    digits: Option<usize>,
+    count: Option<usize>,
+}
+
+impl Parser {
+    pub fn parse(&mut self, evt: &Event) -> Option<Command> {
+        let key = match evt {
+            Event::Key(key) => *key,
+            _ => return None,
+        };
+        if let (true, Char(c @ '0'..='9')) = (self.pending.is_empty(), key.code)
+        {
+            if key.modifiers == KeyModifiers::NONE {
+                let digit = c as usize - '0' as usize;
+                let n = self.digits.unwrap_or(0).saturating_mul(10);
+                self.digits = Some(n.saturating_add(digit));
+                return None;
+            }
+        }
+        self.pending.push(key);
+        if let Some(binding) = KEYMAP.iter().find(|b| b.matches(&self.pending))
+        {
+            self.pending.clear();
+            self.count = self.digits.take().filter(|&n| n > 0);
+            return Some(binding.command);
+        }
+        if !KEYMAP.iter().any(|b| b.starts_with(&self.pending)) {
+            self.pending.clear();
+            self.digits = None;
+        }
+        None
+    }
+
+    // The numeric prefix typed before the last returned command.
+    pub fn take_count(&mut self) -> Option<usize> {
+        self.count.take()
+    }
+
+    pub fn has_count(&self) -> bool {
+        self.count.is_some()
+    }
+}
+
+// Every command once, with all the keys bound to it.
+fn commands() -> Vec<(Command, Vec<String>)> {
+    let mut commands: Vec<(Command, Vec<String>)> = vec![];
+    for binding in KEYMAP {
+        match commands.iter_mut().find(|(cmd, _)| *cmd == binding.command) {
+            Some((_, keys)) => keys.push(binding.name()),
+            None => commands.push((binding.command, vec![binding.name()])),
+        }
+    }
+    commands
+}
+
+fn help_line(cmd: &Command, keys: &[String]) -> String {
+    format!("  {:<20}{}", keys.join(", "), cmd.description())
+}
+
+pub fn help_lines() -> Vec<String> {
+    commands()
+        .iter()
+        .map(|(cmd, keys)| help_line(cmd, keys))
+        .collect()
+}
+
+// Whether the letters of `query` appear in `text` in order, ignoring case
+// and spaces, and if so how spread out they are and where they start:
+// lower is closer. Each place the first letter occurs is tried, so "line"
+// finds "line" in "Toggle line numbers" rather than starting at "le".
+fn fuzzy(query: &str, text: &str) -> Option<(usize, usize)> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let query: Vec<char> = query
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let spread = |start: usize| {
+        let mut spread = 0;
+        let mut next = start;
+        for q in &query {
+            let i = next + text[next..].iter().position(|c| c == q)?;
+            spread += i - next;
+            next = i + 1;
+        }
+        Some((spread, start))
+    };
+    match query.first() {
+        Some(first) => (0..text.len())
+            .filter(|&i| text[i] == *first)
+            .filter_map(spread)
+            .min(),
+        None => Some((0, 0)),
+    }
+}
+
+// The commands whose description matches `query`, closest first, with
+// their help lines.
+pub fn find(query: &str) -> Vec<(Command, String)> {
+    let mut found: Vec<((usize, usize), Command, String)> = commands()
+        .iter()
+        .filter_map(|(cmd, keys)| {
+            let spread = fuzzy(query, cmd.description())?;
+            Some((spread, *cmd, help_line(cmd, keys)))
+        })
+        .collect();
+    found.sort_by_key(|(spread, ..)| *spread);
+    found
+        .into_iter()
+        .map(|(_, cmd, line)| (cmd, line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(parser: &mut Parser, keys: &str) -> Option<Command> {
+        let mut cmd = None;
+        for c in keys.chars() {
+            let key = KeyEvent::new(Char(c), KeyModifiers::NONE);
+            cmd = parser.parse(&Event::Key(key));
+        }
+        cmd
+    }
+
+    #[test]
+    fn parser_counts() {
+        let mut parser = Parser::default();
+        let cmd = press(&mut parser, "12j");
+        assert!(cmd == Some(Command::V(VerticalMove::LineDown)));
+        assert!(parser.has_count());
+        assert_eq!(parser.take_count(), Some(12));
+        assert_eq!(parser.take_count(), None);
+        // A zero count is no count.
+        press(&mut parser, "0j");
+        assert_eq!(parser.take_count(), None);
+        press(&mut parser, "j");
+        assert!(!parser.has_count());
+    }
+
+    #[test]
+    fn parser_prefixes() {
+        let mut parser = Parser::default();
+        assert!(press(&mut parser, "z").is_none());
+        let cmd = press(&mut parser, "t");
+        assert!(cmd == Some(Command::Reposition(Anchor::Top)));
+        let cmd = press(&mut parser, "3zz");
+        assert!(cmd == Some(Command::Reposition(Anchor::Center)));
+        assert_eq!(parser.take_count(), Some(3));
+        // An unbound sequence is dropped along with its count, and the
+        // next key starts afresh.
+        assert!(press(&mut parser, "5zq").is_none());
+        let cmd = press(&mut parser, "q");
+        assert!(cmd == Some(Command::Quit));
+        assert_eq!(parser.take_count(), None);
+        let cmd = press(&mut parser, "zb");
+        assert!(cmd == Some(Command::Reposition(Anchor::Bottom)));
+    }
+
+    #[test]
+    fn fuzzy_ranking() {
+        assert_eq!(fuzzy("", "Quit"), Some((0, 0)));
+        assert_eq!(fuzzy("QUIT", "Quit"), Some((0, 0)));
+        assert_eq!(fuzzy("qt", "Quit"), Some((2, 0)));
+        assert_eq!(fuzzy("tq", "Quit"), None);
+        // Spaces in the query are ignored; in the text they count as gaps.
+        assert_eq!(fuzzy("linenumbers", "Toggle line numbers"), Some((1, 7)));
+        assert_eq!(fuzzy("line num", "Toggle line numbers"), Some((1, 7)));
+        // The closest occurrence wins over the first.
+        assert_eq!(fuzzy("line", "Toggle line numbers"), Some((0, 7)));
+        assert_eq!(fuzzy("le", "Toggle line numbers"), Some((0, 4)));
+    }
+
+    #[test]
+    fn find_orders_closest_first() {
+        let found = find("line numbers");
+        assert!(found[0].0 == Command::ToggleLineNumbers);
+        assert!(found[1].0 == Command::ToggleRelativeNumbers);
+        assert!(found[0].1.starts_with("  l "));
+        assert!(find("quit")[0].0 == Command::Quit);
+        assert!(find("zzzz").is_empty());
+        // Each command is listed once, with all its keys.
+        let found = find("scroll one line down");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].1.contains("j, Down"));
+        assert_eq!(find("").len(), commands().len());
+    }
+}
@@ -0,0 +1,2 @@
+// The parts of morr that are benchmarked on their own.
+pub mod line_reader;
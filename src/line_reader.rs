@@ -1,8 +1,10 @@
-use memchr::{memchr_iter, Memchr};
+use encoding_rs::{Encoding, UTF_8};
+use memchr::{memchr, memchr_iter, memrchr, Memchr};
+use std::borrow::Cow;
 use std::cmp::min;
 use std::iter;
 use std::ops::Range;
-use std::str;
+use std::option;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Sign {
@@ -70,7 +72,6 @@ impl LinesRange {
         }
     }
 
-    #[allow(unused)]
     pub fn extendr(&self, by: usize) -> LinesRange {
         match self.sign {
             Sign::Pos => LinesRange::pos(extendr(&self.range, by)),
@@ -85,52 +86,103 @@ fn inverted(range: &Range<usize>, len: usize) -> Range<usize> {
     start..end
 }
 
+// Clamps a range of lines to the `to` lines there are. A range past the end
+// is moved back onto the last line so reading beyond the end still shows
+// something, but an empty range stays empty and start never passes end.
 fn limit(range: &Range<usize>, to: usize) -> Range<usize> {
+    if range.start >= range.end {
+        let start = min(to, range.start);
+        return start..start;
+    }
     min(to.saturating_sub(1), range.start)..min(to, range.end)
 }
 
 pub struct ReadLines<'a> {
     pub range: LinesRange,
-    pub lines: Vec<&'a str>,
+    pub lines: Vec<Cow<'a, str>>,
     pub buf_range: Range<usize>,
+    // How many lines of the file each line stands for, when squeezing.
+    pub repeats: Vec<usize>,
 }
 
-type Eols<'a> =
-    iter::Chain<iter::Chain<iter::Once<usize>, Memchr<'a>>, iter::Once<usize>>;
+type Eols<'a> = iter::Chain<
+    iter::Chain<iter::Once<usize>, Memchr<'a>>,
+    option::IntoIter<usize>,
+>;
 
 pub struct LineReader<'a> {
     eols_forw: Vec<usize>,
     eols_back: Vec<usize>,
     eols_iter: Eols<'a>,
     pub filename: &'a str,
+    // What the lines are decoded from, as they're read. Only charsets that
+    // keep ASCII as it is, so a newline byte always ends a line.
+    pub charset: &'static Encoding,
+    // Whether a run of identical lines reads as its first, like `uniq`.
+    // Set before reading anything.
+    pub squeeze: bool,
+    // The line of the file each squeezed line starts at, as far as they've
+    // been asked for.
+    origins: Vec<usize>,
     buf: &'a [u8],
+    last_eol: usize,
     full: bool,
 }
 
+// Whether the line after the newline at `eol` differs from the one before,
+// a trailing \r aside, so that the newline ends a run.
+fn ends_run(buf: &[u8], eol: usize) -> bool {
+    if eol == usize::MAX || eol + 1 >= buf.len() {
+        return true;
+    }
+    let start = memrchr(b'\n', &buf[..eol]).map_or(0, |i| i + 1);
+    let end = memchr(b'\n', &buf[eol + 1..]).map_or(buf.len(), |i| eol + 1 + i);
+    let (before, after) = (&buf[start..eol], &buf[eol + 1..end]);
+    before.strip_suffix(b"\r").unwrap_or(before)
+        != after.strip_suffix(b"\r").unwrap_or(after)
+}
+
 impl<'a> LineReader<'a> {
     pub fn new(buf: &'a [u8], filename: &'a str) -> Self {
-        let it = iter::once(usize::max_value())
-            .chain(memchr_iter(b'\n', &buf[..]))
-            .chain(iter::once(buf.len()));
+        // A trailing newline terminates the last line rather than starting
+        // an empty one.
+        let last_eol = match buf.last() {
+            Some(b'\n') => buf.len() - 1,
+            _ => buf.len(),
+        };
+        let it = iter::once(usize::MAX)
+            .chain(memchr_iter(b'\n', buf))
+            .chain(Some(buf.len()).filter(|&len| len == last_eol));
         LineReader {
             eols_forw: vec![],
             eols_back: vec![],
             eols_iter: it,
-            buf: buf,
-            filename: filename,
+            buf,
+            last_eol,
+            filename,
+            charset: UTF_8,
+            squeeze: false,
+            origins: vec![],
             full: false,
         }
     }
 
+    // Skips the newlines inside runs when given the buffer to squeeze.
     fn extend<I: Iterator<Item = usize>>(
         eols: &mut Vec<usize>,
         it: &mut I,
         last_requested_line: usize,
         stop: usize,
+        squeeze: Option<&[u8]>,
     ) -> bool {
-        if let Some(n) = (last_requested_line + 1).checked_sub(eols.len()) {
+        let n = last_requested_line
+            .saturating_add(1)
+            .checked_sub(eols.len());
+        if let Some(n) = n {
             for _ in 0..n {
-                if let Some(idx) = it.next() {
+                let kept =
+                    |&idx: &usize| squeeze.is_none_or(|buf| ends_run(buf, idx));
+                if let Some(idx) = it.find(kept) {
                     eols.push(idx);
                 } else {
                     return true;
@@ -141,6 +193,91 @@ impl<'a> LineReader<'a> {
         false
     }
 
+    pub fn buf(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    // Both ends of the scan have met: join them into a single index.
+    fn complete(&mut self) {
+        self.eols_forw.extend(self.eols_back.iter().rev());
+        self.eols_back.clear();
+        self.full = true;
+    }
+
+    pub fn total_lines(&mut self) -> usize {
+        self.index(usize::MAX);
+        self.eols_forw.len() - 1
+    }
+
+    fn squeezed_buf(&self) -> Option<&'a [u8]> {
+        Some(self.buf).filter(|_| self.squeeze)
+    }
+
+    // The line of the file that `line` starts at, which is `line` itself
+    // unless squeezing.
+    pub fn origin(&mut self, line: usize) -> usize {
+        if !self.squeeze {
+            return line;
+        }
+        self.read_forw(&(line..line + 1));
+        let eols = &self.eols_forw;
+        while self.origins.len() <= line && self.origins.len() < eols.len() {
+            let origin = match self.origins.len() {
+                0 => 0,
+                k => {
+                    let run = &self.buf[eols[k - 1].wrapping_add(1)..eols[k]];
+                    self.origins[k - 1] + 1 + memchr_iter(b'\n', run).count()
+                }
+            };
+            self.origins.push(origin);
+        }
+        self.origins.get(line).copied().unwrap_or(line)
+    }
+
+    // The line that line `line` of the file was squeezed into.
+    pub fn squeezed(&mut self, line: usize) -> usize {
+        if !self.squeeze {
+            return line;
+        }
+        // No line starts further in than its number.
+        self.origin(line);
+        self.origins.partition_point(|&origin| origin <= line) - 1
+    }
+
+    // The number of lines, if the whole file has been indexed already.
+    pub fn indexed(&self) -> Option<usize> {
+        Some(self.eols_forw.len() - 1).filter(|_| self.full)
+    }
+
+    // Counts the lines if that takes indexing no more than `budget` of
+    // them, so a huge file can't stall the caller. None when it runs out;
+    // the lines indexed so far are kept for the next try.
+    pub fn count_lines(&mut self, budget: usize) -> Option<usize> {
+        match self.index(budget) {
+            true => Some(self.eols_forw.len() - 1),
+            false => None,
+        }
+    }
+
+    // Indexes up to `step` more lines from the front, so the whole file can
+    // be indexed a bit at a time. Returns whether it's all indexed.
+    pub fn index(&mut self, step: usize) -> bool {
+        let last = self.eols_forw.len().saturating_add(step);
+        let squeeze = self.squeezed_buf();
+        if !self.full
+            && Self::extend(
+                &mut self.eols_forw,
+                &mut self.eols_iter,
+                last,
+                self.last_eol,
+                squeeze,
+            )
+        {
+            self.complete();
+        }
+        self.full
+    }
+
     pub fn read(&mut self, range: &LinesRange) -> ReadLines<'a> {
         match range.sign {
             Sign::Pos => self.read_forw(&range.range),
@@ -149,17 +286,17 @@ impl<'a> LineReader<'a> {
     }
 
     fn read_forw(&mut self, range: &Range<usize>) -> ReadLines<'a> {
+        let squeeze = self.squeezed_buf();
         if !self.full
             && Self::extend(
                 &mut self.eols_forw,
                 &mut self.eols_iter,
                 range.end,
-                self.buf.len(),
+                self.last_eol,
+                squeeze,
             )
         {
-            self.eols_forw.extend(self.eols_back.iter().rev());
-            self.eols_back.clear();
-            self.full = true;
+            self.complete();
         }
         let available_lines = self.eols_forw.len().saturating_sub(1);
         let range = limit(range, available_lines);
@@ -170,21 +307,22 @@ impl<'a> LineReader<'a> {
             range: LinesRange::pos(range),
             buf_range: s..e,
             lines: self.lines(slice),
+            repeats: self.repeats(slice),
         }
     }
 
     fn read_back(&mut self, range: &Range<usize>) -> ReadLines<'a> {
+        let squeeze = self.squeezed_buf();
         if self.full
             || Self::extend(
                 &mut self.eols_back,
                 &mut (&mut self.eols_iter).rev(),
                 range.end,
-                self.buf.len(),
+                usize::MAX,
+                squeeze,
             )
         {
-            self.eols_forw.extend(self.eols_back.iter().rev());
-            self.eols_back.clear();
-            self.full = true;
+            self.complete();
             return self.read_forw(&inverted(range, self.eols_forw.len() - 1));
         }
         let available_lines = self.eols_back.len().saturating_sub(1);
@@ -198,16 +336,238 @@ impl<'a> LineReader<'a> {
             range: LinesRange::neg(range),
             buf_range: s..e,
             lines: self.lines(&requested_eols),
+            repeats: self.repeats(&requested_eols),
         }
     }
 
-    fn lines(&self, requested_eols: &[usize]) -> Vec<&'a str> {
+    // Borrowed from the buffer unless decoding changed them. A squeezed
+    // run reads as its first line.
+    fn lines(&self, requested_eols: &[usize]) -> Vec<Cow<'a, str>> {
+        let buf = self.buf;
+        let first = |line: &'a [u8]| match self.squeeze {
+            true => line.split(|&b| b == b'\n').next().unwrap_or(line),
+            false => line,
+        };
+        requested_eols
+            .windows(2)
+            .map(|p| first(&buf[p[0].overflowing_add(1).0..p[1]]))
+            .map(|line| self.charset.decode_without_bom_handling(line).0)
+            .collect()
+    }
+
+    fn repeats(&self, requested_eols: &[usize]) -> Vec<usize> {
+        if !self.squeeze {
+            return vec![];
+        }
         requested_eols
             .windows(2)
-            .map(|p| p[0].overflowing_add(1).0..p[1])
-            .map(|range| &self.buf[range])
-            .map(str::from_utf8)
-            .map(Result::unwrap)
+            .map(|p| &self.buf[p[0].wrapping_add(1)..p[1]])
+            .map(|run| 1 + memchr_iter(b'\n', run).count())
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lines of varying length, some of them empty.
+    fn text(lines: usize, trailing_newline: bool) -> String {
+        let mut text: Vec<String> = (0..lines)
+            .map(|i| "x".repeat(i * 7 % 5) + &i.to_string().repeat(i % 3))
+            .collect();
+        if trailing_newline {
+            text.push(String::new());
+        }
+        text.join("\n")
+    }
+
+    fn texts() -> Vec<String> {
+        let sizes = [0, 1, 2, 5, 17, 64];
+        let texts = sizes
+            .iter()
+            .flat_map(|&n| vec![text(n, false), text(n, true)]);
+        texts.collect()
+    }
+
+    #[test]
+    fn limit_keeps_empty_ranges_empty() {
+        assert_eq!(limit(&(3..3), 10), 3..3);
+        assert_eq!(limit(&(0..0), 10), 0..0);
+        assert_eq!(limit(&(12..12), 10), 10..10);
+    }
+
+    #[test]
+    fn limit_with_no_lines() {
+        assert_eq!(limit(&(0..5), 0), 0..0);
+        assert_eq!(limit(&(3..3), 0), 0..0);
+        assert_eq!(limit(&(7..9), 0), 0..0);
+    }
+
+    #[test]
+    fn limit_moves_ranges_past_the_end_onto_the_last_line() {
+        assert_eq!(limit(&(20..25), 10), 9..10);
+        assert_eq!(limit(&(10..11), 10), 9..10);
+        assert_eq!(limit(&(8..15), 10), 8..10);
+        assert_eq!(limit(&(2..5), 10), 2..5);
+    }
+
+    #[test]
+    fn limit_never_starts_past_its_end() {
+        for to in 0..6 {
+            for start in 0..8 {
+                for end in 0..8 {
+                    let limited = limit(&(start..end), to);
+                    assert!(limited.start <= limited.end, "{:?}", limited);
+                    assert!(limited.end <= to, "{:?}", limited);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn backward_reads_cover_the_file() {
+        for text in texts() {
+            let expected: Vec<&str> = text.lines().collect();
+            for chunk in 1..8 {
+                let mut reader = LineReader::new(text.as_bytes(), "test");
+                let mut lines: Vec<Cow<str>> = vec![];
+                for start in (0..expected.len()).step_by(chunk) {
+                    let read =
+                        reader.read(&LinesRange::neg(start..start + chunk));
+                    let mut chunk_lines = read.lines;
+                    chunk_lines.append(&mut lines);
+                    lines = chunk_lines;
+                }
+                assert_eq!(lines, expected, "{:?} by {}", text, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn backward_reads_past_the_top_match_a_forward_read() {
+        for text in texts() {
+            let len = text.lines().count();
+            let forward = LineReader::new(text.as_bytes(), "test")
+                .read(&LinesRange::pos(0..len))
+                .lines;
+            for start in 0..len {
+                for end in len.max(start + 1)..len + 3 {
+                    // Fresh, and with the file already indexed.
+                    for indexed in [false, true] {
+                        let mut reader =
+                            LineReader::new(text.as_bytes(), "test");
+                        if indexed {
+                            reader.total_lines();
+                        }
+                        let read = reader.read(&LinesRange::neg(start..end));
+                        assert_eq!(
+                            read.lines,
+                            &forward[..len - start],
+                            "{:?} {}..{}",
+                            text,
+                            start,
+                            end
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // What G reads: the last screenful, i.e. `neg(0..rows)`.
+    #[test]
+    fn bottom_shows_the_last_line_last() {
+        let rows = 10;
+        for len in [rows - 1, rows, rows + 1] {
+            for trailing_newline in [false, true] {
+                let text = text(len, trailing_newline);
+                let expected: Vec<&str> = text.lines().collect();
+                let shown = &expected[len.saturating_sub(rows)..];
+                // Straight after opening, and after showing the first page.
+                for first_page in [false, true] {
+                    let mut reader = LineReader::new(text.as_bytes(), "test");
+                    if first_page {
+                        reader.read(&LinesRange::pos(0..rows));
+                    }
+                    let read = reader.read(&LinesRange::neg(0..rows));
+                    assert_eq!(read.lines, shown, "{} lines", len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lines_are_decoded_as_they_are_read() {
+        let buf = b"\x93\xfa\x96{\x8c\xea\nascii\nna\xefve";
+        let mut reader = LineReader::new(buf, "test");
+        reader.charset = encoding_rs::SHIFT_JIS;
+        let read = reader.read(&LinesRange::pos(0..2));
+        assert_eq!(read.lines, ["日本語", "ascii"]);
+        assert!(matches!(read.lines[1], Cow::Borrowed(_)));
+        reader.charset = encoding_rs::WINDOWS_1252;
+        let read = reader.read(&LinesRange::neg(0..1));
+        assert_eq!(read.lines, ["naïve"]);
+        // Not valid UTF-8 either, but no longer a panic.
+        reader.charset = UTF_8;
+        let read = reader.read(&LinesRange::neg(0..1));
+        assert_eq!(read.lines, ["na\u{fffd}ve"]);
+    }
+
+    #[test]
+    fn squeezed_runs_read_as_their_first_line() {
+        let texts = [
+            "a\na\na\nb\na\na",
+            "a\na\r\nb\nb\n",
+            "\n\n\nx\n\n",
+            "same\nsame\nsame\n",
+        ];
+        for text in texts {
+            let lines: Vec<&str> = text.lines().collect();
+            // What `uniq -c` would print.
+            let mut expected: Vec<(&str, usize, usize)> = vec![];
+            for (i, line) in lines.iter().enumerate() {
+                match expected.last_mut() {
+                    Some((last, n, _)) if *last == *line => *n += 1,
+                    _ => expected.push((line, 1, i)),
+                }
+            }
+            let total = expected.len();
+            for chunk in 1..4 {
+                let mut reader = LineReader::new(text.as_bytes(), "test");
+                reader.squeeze = true;
+                let mut back: Vec<(String, usize)> = vec![];
+                for start in (0..total).step_by(chunk) {
+                    let read =
+                        reader.read(&LinesRange::neg(start..start + chunk));
+                    let read = read.lines.iter().zip(read.repeats);
+                    let read =
+                        read.map(|(line, n)| (line.trim_end().to_owned(), n));
+                    back.splice(0..0, read);
+                }
+                let forw = reader.read(&LinesRange::pos(0..total));
+                let forw: Vec<(String, usize)> = forw
+                    .lines
+                    .iter()
+                    .zip(forw.repeats)
+                    .map(|(line, n)| (line.trim_end().to_owned(), n))
+                    .collect();
+                let uniq: Vec<(String, usize)> = expected
+                    .iter()
+                    .map(|&(line, n, _)| (line.to_owned(), n))
+                    .collect();
+                assert_eq!(forw, uniq, "{:?}", text);
+                assert_eq!(back, uniq, "{:?} by {}", text, chunk);
+                assert_eq!(reader.total_lines(), total);
+                for (i, &(_, _, origin)) in expected.iter().enumerate() {
+                    assert_eq!(reader.origin(i), origin, "{:?}", text);
+                }
+                for (i, _) in lines.iter().enumerate() {
+                    let line = reader.squeezed(i);
+                    let (_, n, origin) = expected[line];
+                    assert!((origin..origin + n).contains(&i), "{:?}", text);
+                }
+            }
+        }
+    }
+}
@@ -1,60 +1,785 @@
-use crossterm::event::Event::Key;
-use crossterm::event::KeyCode::{
-    Char, Down, Left, PageDown, PageUp, Right, Up,
-};
-use crossterm::event::{Event, KeyEvent, KeyModifiers};
-use crossterm::{cursor, event, queue, style, terminal};
+use crossterm::event::{Event, KeyCode, KeyEvent};
+use crossterm::{cursor, event, execute, queue, style, terminal};
+use encoding_rs::{CoderResult, Encoding, UTF_8};
 use memmap::Mmap;
+use signal_hook::consts::{SIGHUP, SIGQUIT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::borrow::Cow;
 use std::env;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::{stdout, Stdout, Write};
+use std::io::{stdout, IsTerminal, Read, Stdout, Write};
 use std::iter;
+use std::ops::{Deref, Range};
+use std::process;
+use std::thread;
+use std::time::Duration;
 
-mod line_reader;
+mod args;
+mod filter;
+mod history;
+mod keymap;
+mod minimap;
+mod options;
+mod palette;
+mod patterns;
+mod prompt;
+mod render;
+mod search;
+mod timestamp;
 
-use line_reader::{LineReader, LinesRange, ReadLines};
+use morr::line_reader;
+use morr::line_reader::{LineReader, LinesRange, ReadLines, Sign};
+use options::{LineNumbers, Options, Theme};
+use palette::Depth;
+use patterns::Highlight;
+use prompt::{PromptMode, PromptResult};
+use render::{Format, Mark, Rendered};
+use search::{IncSearch, Match};
 
 fn main() -> Result<(), DrawError> {
-    let filename = env::args().nth(1).expect("No file name passed");
-    let buf = File::open(&filename)
-        .and_then(|file| unsafe { Mmap::map(&file) })
-        .unwrap();
-    let mut screen = ConsoleScreen::init().unwrap();
-    let events = iter::repeat_with(event::read).flatten();
-    let commands = parse(events);
-    let rows = screen.rows();
-    let mut line_reader = LineReader::new(&buf, &filename);
-    let lines = line_reader.read(&LinesRange::pos(0..rows));
-    let mut mode = NormalMode {
-        line_reader: &mut line_reader,
-        current_range: lines.range.clone(),
+    let args = args::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("morr: {}\n{}", e, args::USAGE);
+        process::exit(2)
+    });
+    let filename = args.filename;
+    let loading = format!("Loading {}…", filename);
+    let interactive = interactive() && args.lines.is_none();
+    if interactive {
+        let _ = execute!(stdout(), style::Print(&loading));
+    }
+    let buf = match &args.filter {
+        Some(cmd) => filter::file(cmd, &filename).map(Buffer::Read),
+        None => open(&filename)
+            .map_err(|e| format!("{}: {}", filename, describe(&e))),
+    };
+    if interactive {
+        let _ = clear_line();
+    }
+    let buf = buf.unwrap_or_else(|e| {
+        eprintln!("morr: {}", e);
+        process::exit(1)
+    });
+    let patterns = match &args.pattern_file {
+        Some(path) => patterns::load(path).unwrap_or_else(|e| {
+            eprintln!("morr: {}", e);
+            process::exit(2)
+        }),
+        None => vec![],
+    };
+    let buf = match args.bytes.clone() {
+        Some(range) => window(&buf, range, args.charset).unwrap_or_else(|e| {
+            eprintln!("morr: {}: {}", filename, e);
+            process::exit(1)
+        }),
+        None => &buf,
+    };
+    let mut line_reader = LineReader::new(buf, &filename);
+    line_reader.charset = args.charset;
+    line_reader.squeeze = args.squeeze;
+    if let Some(range) = args.lines {
+        return extract(&mut line_reader, range);
+    }
+    if !interactive
+        || args.quit_if_one_screen && fits_one_screen(&mut line_reader)?
+    {
+        return dump(&mut line_reader);
+    }
+    let depth = palette::detect(args.color);
+    let mut screen =
+        ConsoleScreen::init(!args.no_alternate_screen, args.theme, depth)
+            .unwrap();
+    let mut rows = screen.rows();
+    let mut cols = screen.cols();
+    let help = keymap::help_lines();
+    // The first read scans the file for line ends; with --end that's all of
+    // it, so keep saying what's going on until the first frame replaces it.
+    screen.draw(DrawCommand::DrawStatus {
+        status: Cow::Borrowed(&loading),
+    })?;
+    // Positions within a byte window, among squeezed lines or in the output
+    // of a filter aren't positions in the file.
+    let history_key = match args.no_history
+        || args.bytes.is_some()
+        || args.squeeze
+        || args.filter.is_some()
+    {
+        true => None,
+        false => history::key(&filename),
     };
+    let top = history_key.as_deref().and_then(history::position);
+    let (lines, cursor) =
+        first_page(&mut line_reader, args.end, top.unwrap_or(0), rows);
+    let options = Options {
+        line_numbers: match args.number_nonblank {
+            true => LineNumbers::NonBlank,
+            false => LineNumbers::Off,
+        },
+        scroll: args.scroll,
+        scrolloff: args.scrolloff,
+        hscroll: args.hscroll,
+        raw: args.raw,
+        links: args.hyperlinks,
+        scrollbar: args.scrollbar,
+        group: args.group.is_some(),
+        continuation: args.group.clone().unwrap_or_default(),
+        theme: args.theme,
+        ..Options::default()
+    };
+    let mut mode = NormalMode::new(&mut line_reader, options);
+    mode.current_range = lines.range.clone();
+    mode.cursor = cursor;
+    mode.filler = &args.filler;
+    mode.patterns = &patterns;
+    mode.scan_budget = args.max_forward_scan;
     draw(&mut screen, mode.mk_draw_commands(lines))?;
-    let draw_commands = commands
-        .take_while(|cmd| !matches!(cmd, Command::Quit))
-        .map(|cmd| match cmd {
-            Command::V(vmove) => mode.process_move(vmove, rows),
-            _ => vec![],
-        });
-    for commands in draw_commands {
-        draw(&mut screen, commands)?;
+    let mut overlay = false;
+    // The first line of the help shown, while it's open.
+    let mut help_top: Option<usize> = None;
+    let mut prompt: Option<(PromptMode, PromptAction)> = None;
+    let mut parser = keymap::Parser::default();
+    let mut backlog = None;
+    let mut last: Option<(Command, Option<usize>)> = None;
+    loop {
+        let (evt, parsed) = match backlog.take() {
+            Some((evt, cmd)) => (evt, Some(cmd)),
+            None => {
+                if !event::poll(Duration::from_secs(0))? {
+                    mode.read_ahead(rows);
+                }
+                // Index the rest of the file while idle when a count is
+                // waiting for it, or always with --idle-index. A step at a
+                // time, so a key press is never kept waiting for more.
+                let step = args.max_forward_scan;
+                while (mode.indexing || args.idle_index)
+                    && !event::poll(Duration::from_secs(0))?
+                {
+                    if mode.line_reader.index(step) {
+                        let waiting = std::mem::take(&mut mode.indexing);
+                        if waiting && !overlay && prompt.is_none() {
+                            draw(&mut screen, mode.redraw())?;
+                        }
+                        break;
+                    }
+                }
+                (event::read()?, None)
+            }
+        };
+        if let Event::Resize(new_cols, new_rows) = evt {
+            screen.resize(new_cols, new_rows);
+            rows = screen.rows();
+            cols = screen.cols();
+            overlay = false;
+            help_top = None;
+            draw(&mut screen, mode.resize(rows))?;
+            if let Some((input, _)) = &prompt {
+                draw(&mut screen, input.mk_draw_commands())?;
+            }
+            continue;
+        }
+        let draw_commands = if let Some((mut input, action)) = prompt.take() {
+            let mut action = action;
+            let result = match &evt {
+                Event::Key(key) if select(&mut action, key) => {
+                    Some(PromptResult::Editing)
+                }
+                Event::Key(key) => Some(input.process_key(*key)),
+                Event::Paste(text) => Some(input.paste(text)),
+                _ => None,
+            };
+            match result {
+                Some(PromptResult::Editing) => {
+                    let draw_commands =
+                        mode.edit(&mut action, input.input(), rows);
+                    prompt = Some((input, action));
+                    draw_commands
+                }
+                Some(PromptResult::Cancel) => mode.cancel(action),
+                Some(PromptResult::Submit(text)) => {
+                    if let PromptAction::Search { .. } = action {
+                        last = Some((Command::SearchNext, None));
+                    }
+                    let cmd = match action {
+                        PromptAction::Execute { selected } => {
+                            palette(&text, selected)
+                        }
+                        _ => None,
+                    };
+                    match cmd {
+                        // Run as though its key had been pressed.
+                        Some(cmd) => {
+                            backlog = Some((evt, Some(cmd)));
+                            mode.redraw()
+                        }
+                        _ => {
+                            let (next, draw_commands) =
+                                mode.submit(action, text, rows);
+                            prompt = next;
+                            draw_commands
+                        }
+                    }
+                }
+                None => {
+                    prompt = Some((input, action));
+                    vec![]
+                }
+            }
+        } else {
+            let mut cmd = parsed.unwrap_or_else(|| parser.parse(&evt));
+            let mut prefix = parser.take_count();
+            if cmd == Some(Command::Repeat) {
+                if let Some((last_cmd, last_prefix)) = last {
+                    cmd = Some(last_cmd);
+                    prefix = prefix.or(last_prefix);
+                }
+            }
+            // A key pressed over an overlay only closes it (or pages the
+            // help), so there's nothing for `.` to repeat.
+            let on_overlay = overlay && matches!(evt, Event::Key(_));
+            if let Some(cmd) = cmd.filter(Command::repeatable) {
+                if !on_overlay {
+                    last = Some((cmd, prefix));
+                }
+            }
+            let page = match cmd {
+                Some(Command::V(VerticalMove::PageDown)) => Some(true),
+                Some(Command::V(VerticalMove::PageUp)) => Some(false),
+                _ => None,
+            };
+            match cmd {
+                _ if on_overlay => match (help_top.take(), page) {
+                    (Some(top), Some(down)) => {
+                        let top = match down {
+                            true => top + rows,
+                            false => top.saturating_sub(rows),
+                        };
+                        let top = top.min(help.len().saturating_sub(1));
+                        help_top = Some(top);
+                        help_page(&help, top, rows)
+                    }
+                    _ => {
+                        overlay = false;
+                        mode.redraw()
+                    }
+                },
+                Some(Command::Repeat) => mode.notify("Nothing to repeat"),
+                Some(Command::Quit) => break,
+                Some(Command::Help) => {
+                    help_top = Some(0);
+                    help_page(&help, 0, rows)
+                }
+                Some(cmd @ Command::V(vmove)) => {
+                    let count = coalesce(&mut parser, cmd, &mut backlog)?;
+                    mode.process_move(vmove, rows, count, prefix)
+                }
+                Some(Command::H(hmove)) => mode.process_hmove(hmove, cols),
+                Some(Command::Reposition(anchor)) => {
+                    mode.reposition(anchor, rows)
+                }
+                Some(Command::ToggleLineNumbers) => mode.toggle_line_numbers(),
+                Some(Command::ToggleRelativeNumbers) => {
+                    mode.toggle_relative_numbers()
+                }
+                Some(Command::Ruler) => mode.show_ruler(cols),
+                Some(Command::ToggleScrollbar) => mode.toggle_scrollbar(),
+                Some(Command::ToggleCenter) => mode.toggle_center(),
+                Some(Command::ToggleMinimap) => mode.toggle_minimap(),
+                Some(Command::ToggleTheme) => mode.toggle_theme(),
+                Some(Command::Search) => match mode.start_search() {
+                    Some(action) => {
+                        prompt = Some((PromptMode::new("/"), action));
+                        vec![]
+                    }
+                    None => mode.draw_status(),
+                },
+                Some(Command::MatchBracket) => mode.match_bracket(rows),
+                Some(Command::HexDump) => mode.hex_dump(),
+                Some(Command::ClearHighlight) => mode.clear_highlight(),
+                Some(Command::SearchNext) => mode.find_next(false, rows),
+                Some(Command::SearchPrev) => mode.find_next(true, rows),
+                Some(Command::SearchWord) => mode.search_word(false, rows),
+                Some(Command::SearchWordBack) => mode.search_word(true, rows),
+                Some(Command::Filter) => {
+                    let input = PromptMode::new("|");
+                    prompt = Some((input, PromptAction::Filter));
+                    vec![]
+                }
+                Some(Command::PipeLine) => {
+                    let input = PromptMode::new("!");
+                    prompt = Some((input, PromptAction::PipeLine));
+                    vec![]
+                }
+                Some(Command::GotoTime) => {
+                    let input = PromptMode::new("Time: ");
+                    prompt = Some((input, PromptAction::GotoTime));
+                    vec![]
+                }
+                Some(Command::Execute) => {
+                    let input = PromptMode::new(":");
+                    prompt =
+                        Some((input, PromptAction::Execute { selected: 0 }));
+                    vec![]
+                }
+                Some(Command::Save(scope)) => {
+                    let input = PromptMode::new("Save to: ");
+                    prompt = Some((input, PromptAction::Save(scope)));
+                    vec![]
+                }
+                _ => vec![],
+            }
+        };
+        // Until a key is pressed, unless the file is drawn over it first.
+        for cmd in &draw_commands {
+            match cmd {
+                DrawCommand::DrawOverlay { .. } => overlay = true,
+                DrawCommand::DrawContent { .. } => overlay = false,
+                _ => {}
+            }
+        }
+        draw(&mut screen, draw_commands)?;
+        if let Some((input, _)) = &prompt {
+            draw(&mut screen, input.mk_draw_commands())?;
+        }
     }
+    // Not worth keeping the user waiting for at the end.
+    let range = mode.current_range.clone();
+    if let (Some(key), Some(top)) = (&history_key, mode.known_first(&range)) {
+        history::save(key, top + mode.offset);
+    }
+    screen.cleanup();
     Ok(())
 }
 
+enum Buffer {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Buffer::Mapped(mmap) => mmap,
+            Buffer::Read(vec) => vec,
+        }
+    }
+}
+
+// Pipes, character devices and empty files can't be mapped, so they are read
+// into memory instead.
+fn open(filename: &str) -> io::Result<Buffer> {
+    let mut file = File::open(filename)?;
+    let meta = file.metadata()?;
+    if meta.is_dir() {
+        return Err(io::ErrorKind::IsADirectory.into());
+    }
+    if meta.is_file() && meta.len() > 0 {
+        return unsafe { Mmap::map(&file) }.map(Buffer::Mapped);
+    }
+    let mut buf = vec![];
+    file.read_to_end(&mut buf)?;
+    Ok(Buffer::Read(buf))
+}
+
+// The bytes asked for with --bytes, moved inwards to the nearest character
+// boundaries so that the window doesn't cut a UTF-8 sequence in two. Other
+// charsets are cut where asked.
+fn window<'b>(
+    buf: &'b [u8],
+    range: Range<usize>,
+    charset: &'static Encoding,
+) -> Result<&'b [u8], String> {
+    if range.start >= buf.len() {
+        return Err(format!("only {} bytes", buf.len()));
+    }
+    let boundary = |i: usize| {
+        charset != UTF_8 || buf.get(i).is_none_or(|&b| b & 0xc0 != 0x80)
+    };
+    let mut start = range.start;
+    while !boundary(start) {
+        start += 1;
+    }
+    let mut end = range.end.min(buf.len());
+    while end > start && !boundary(end) {
+        end -= 1;
+    }
+    Ok(&buf[start..end.max(start)])
+}
+
+fn describe(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => String::from("no such file"),
+        io::ErrorKind::IsADirectory => String::from("is a directory"),
+        io::ErrorKind::PermissionDenied => String::from("permission denied"),
+        _ => e.to_string(),
+    }
+}
+
+// Escape sequences are only useful on a terminal that understands them.
+fn interactive() -> bool {
+    stdout().is_terminal()
+        && env::var("TERM").map_or(true, |term| term != "dumb")
+}
+
+fn clear_line() -> io::Result<()> {
+    execute!(
+        stdout(),
+        cursor::MoveToColumn(0),
+        terminal::Clear(terminal::ClearType::CurrentLine)
+    )
+}
+
+// As UTF-8, decoded as it's written when the file is in another charset,
+// and squeezed when asked to.
+fn dump(line_reader: &mut LineReader) -> Result<(), DrawError> {
+    let buf = line_reader.buf();
+    let written = match (line_reader.squeeze, line_reader.charset) {
+        (true, _) => write_lines(line_reader),
+        (false, charset) if charset == UTF_8 => stdout().write_all(buf),
+        (false, charset) => write_decoded(buf, charset),
+    };
+    match written {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn write_lines(line_reader: &mut LineReader) -> io::Result<()> {
+    const CHUNK: usize = 4096;
+    let mut out = io::BufWriter::new(stdout());
+    let mut start = 0;
+    loop {
+        let lines = line_reader.read(&LinesRange::pos(start..start + CHUNK));
+        // Past the end, the read is clamped to the last line.
+        if lines.lines.is_empty() || lines.range.range.start != start {
+            return out.flush();
+        }
+        for line in &lines.lines {
+            writeln!(out, "{}", line)?;
+        }
+        start = lines.range.range.end;
+    }
+}
+
+// A buffer's worth at a time, however long the file.
+fn write_decoded(buf: &[u8], charset: &'static Encoding) -> io::Result<()> {
+    let mut decoder = charset.new_decoder_without_bom_handling();
+    let mut out = stdout();
+    let mut text = String::with_capacity(1 << 16);
+    let mut rest = buf;
+    loop {
+        text.clear();
+        let (result, read, _) = decoder.decode_to_string(rest, &mut text, true);
+        rest = &rest[read..];
+        out.write_all(text.as_bytes())?;
+        if result == CoderResult::InputEmpty {
+            return Ok(());
+        }
+    }
+}
+
+fn extract(
+    line_reader: &mut LineReader,
+    range: Range<usize>,
+) -> Result<(), DrawError> {
+    let start = range.start;
+    let lines = line_reader.read(&LinesRange::pos(range));
+    // Reads past the end are clamped to the last line rather than failing.
+    if lines.lines.is_empty() || lines.range.range.start != start {
+        let total = lines.range.range.end;
+        eprintln!("morr: {} has only {} lines", line_reader.filename, total);
+        process::exit(1)
+    }
+    let mut out = io::BufWriter::new(stdout());
+    let written = lines
+        .lines
+        .iter()
+        .try_for_each(|line| writeln!(out, "{}", line))
+        .and_then(|_| out.flush());
+    match written {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// The page shown on startup and the row the cursor starts on: the last
+// page with the cursor on the last line for --end, else the page at `top`.
+fn first_page<'a>(
+    line_reader: &mut LineReader<'a>,
+    end: bool,
+    top: usize,
+    rows: usize,
+) -> (ReadLines<'a>, usize) {
+    if end {
+        let lines = line_reader.read(&LinesRange::neg(0..rows));
+        let last = lines.lines.len().saturating_sub(1);
+        (lines, last)
+    } else {
+        (line_reader.read(&LinesRange::pos(top..top + rows)), 0)
+    }
+}
+
+// The lines of the help from `top` that fit on the screen, saying how to
+// page through the rest when it doesn't all fit.
+fn help_page(help: &[String], top: usize, rows: usize) -> Vec<DrawCommand<'_>> {
+    let end = help.len().min(top + rows);
+    let status = match (top, end) {
+        (0, end) if end == help.len() => "Press any key to return".to_owned(),
+        (top, end) => {
+            let keys = match (top > 0, end < help.len()) {
+                (false, _) => "PageDown for more",
+                (true, false) => "PageUp to go back",
+                (true, true) => "PageDown/PageUp to page",
+            };
+            let lines = format!("{}-{} of {}", top + 1, end, help.len());
+            format!("Commands {}: {}, any other key to return", lines, keys)
+        }
+    };
+    vec![
+        DrawCommand::DrawOverlay {
+            lines: Cow::Borrowed(&help[top..end]),
+        },
+        DrawCommand::DrawStatus {
+            status: Cow::Owned(status),
+        },
+    ]
+}
+
+fn fits_one_screen(line_reader: &mut LineReader) -> Result<bool, DrawError> {
+    let (_, height) = terminal::size()?;
+    let height = height as usize;
+    let lines = line_reader.read(&LinesRange::pos(0..height));
+    Ok(lines.lines.len() < height)
+}
+
+const EX_COMMANDS: &[&str] = &["set", "se", "range"];
+
+// What `:` runs when it's given anything but an ex command: the
+// `selected` command among those whose description matches, closest first.
+fn palette(input: &str, selected: usize) -> Option<Command> {
+    let word = input.split_whitespace().next()?;
+    if EX_COMMANDS.contains(&word) {
+        return None;
+    }
+    let found = keymap::find(input);
+    found
+        .get(selected)
+        .or_else(|| found.last())
+        .map(|(cmd, _)| *cmd)
+}
+
+// Up and Down (or Tab and Shift-Tab) move through the palette's
+// candidates; any other key edits the input, so the closest match is
+// selected again. Whether `key` was a move.
+fn select(action: &mut PromptAction, key: &KeyEvent) -> bool {
+    let selected = match action {
+        PromptAction::Execute { selected } => selected,
+        _ => return false,
+    };
+    match key.code {
+        KeyCode::Down | KeyCode::Tab => *selected += 1,
+        KeyCode::Up | KeyCode::BackTab => {
+            *selected = selected.saturating_sub(1)
+        }
+        KeyCode::Enter => return false,
+        _ => {
+            *selected = 0;
+            return false;
+        }
+    }
+    true
+}
+
+fn coalesce(
+    parser: &mut keymap::Parser,
+    cmd: Command,
+    backlog: &mut Option<(Event, Option<Command>)>,
+) -> Result<usize, DrawError> {
+    let mut count = 1;
+    while backlog.is_none() && event::poll(Duration::from_secs(0))? {
+        let evt = event::read()?;
+        match parser.parse(&evt) {
+            Some(next) if next == cmd && !parser.has_count() => count += 1,
+            next => *backlog = Some((evt, next)),
+        }
+    }
+    Ok(count)
+}
+
 struct NormalMode<'a> {
     line_reader: &'a mut LineReader<'a>,
     current_range: LinesRange,
+    col_offset: usize,
+    cursor: usize,
+    message: Option<String>,
+    filler: &'a str,
+    patterns: &'a [Highlight],
+    options: Options,
+    ruler: bool,
+    half_page: Option<usize>,
+    minimap: Option<(MapKey, Vec<minimap::Cell>)>,
+    // Non-blank lines before a line, for numbering them only.
+    nonblank: (usize, usize),
+    // The whole file while `:range` narrows the view to a part of it, which
+    // starts `offset` lines in.
+    full: Option<LineReader<'a>>,
+    offset: usize,
+    // Lines a count may index at once, and whether one ran out and left the
+    // rest for idle time.
+    scan_budget: usize,
+    indexing: bool,
+    pattern: Option<String>,
+    // Set by `*` and `#`, which always match whole words.
+    word_search: bool,
+    current_match: Option<Match>,
+    hilite: bool,
+}
+
+enum PromptAction {
+    // Which of the palette's candidates Enter runs.
+    Execute {
+        selected: usize,
+    },
+    Filter,
+    PipeLine,
+    GotoTime,
+    Save(SaveScope),
+    Overwrite(SaveScope, String),
+    Search {
+        origin: LinesRange,
+        cursor: usize,
+        inc: IncSearch,
+    },
 }
 
 impl<'a> NormalMode<'a> {
+    // At the top of the file, with nothing searched for or highlighted.
+    fn new(line_reader: &'a mut LineReader<'a>, options: Options) -> Self {
+        NormalMode {
+            line_reader,
+            current_range: LinesRange::pos(0..0),
+            col_offset: 0,
+            cursor: 0,
+            message: None,
+            filler: "~",
+            patterns: &[],
+            options,
+            ruler: false,
+            half_page: None,
+            minimap: None,
+            nonblank: (0, 0),
+            scan_budget: 1 << 20,
+            indexing: false,
+            full: None,
+            offset: 0,
+            pattern: None,
+            word_search: false,
+            current_match: None,
+            hilite: true,
+        }
+    }
+
     fn process_move(
         &mut self,
         vmove: VerticalMove,
         rows: usize,
+        count: usize,
+        prefix: Option<usize>,
     ) -> Vec<DrawCommand<'a>> {
-        let read_lines = self.move_and_read(vmove, rows);
+        if let VerticalMove::ParagraphDown | VerticalMove::ParagraphUp = vmove {
+            let forward = vmove == VerticalMove::ParagraphDown;
+            return self.paragraph(forward, rows, prefix.unwrap_or(1) * count);
+        }
+        let count = match vmove {
+            VerticalMove::HalfPageDown | VerticalMove::HalfPageUp => {
+                if prefix.is_some() {
+                    self.half_page = prefix;
+                }
+                self.half_page.unwrap_or((rows / 2).max(1)) * count
+            }
+            VerticalMove::LineDown | VerticalMove::LineUp => {
+                prefix.unwrap_or(1) * count * self.options.scroll
+            }
+            _ => prefix.unwrap_or(1) * count,
+        };
+        let cursor = self.cursor;
+        let visible = self.current_range.range.len();
+        // No more than half the screen, so the cursor can still move.
+        let off = self.options.scrolloff.min(visible.saturating_sub(1) / 2);
+        let by = match vmove {
+            VerticalMove::LineDown => {
+                let room = visible.saturating_sub(cursor + 1 + off);
+                let step = count.min(room);
+                self.cursor += step;
+                count - step
+            }
+            VerticalMove::LineUp => {
+                let step = count.min(cursor.saturating_sub(off));
+                self.cursor -= step;
+                count - step
+            }
+            _ => count,
+        };
+        if by == 0 {
+            return self.redraw();
+        }
+        let new_range = mv(vmove, self.current_range.clone(), rows, by);
+        let read_lines = self.read_filled(new_range);
+        let last = read_lines.lines.len().saturating_sub(1);
+        // What the view couldn't scroll at either end of the file, the
+        // cursor moves into the margin instead.
+        let current_range = self.current_range.clone();
+        let moved = self.distance(&current_range, &read_lines.range);
+        let left = by.saturating_sub(moved.map_or(by, isize::unsigned_abs));
+        self.cursor = match vmove {
+            VerticalMove::Top => 0,
+            VerticalMove::Bottom => last,
+            VerticalMove::LineDown => (self.cursor + left).min(last),
+            VerticalMove::LineUp => self.cursor.saturating_sub(left),
+            _ => self.cursor.min(last),
+        };
+        if self.cursor != cursor && read_lines.range == self.current_range {
+            return self.redraw();
+        }
+        self.show(read_lines)
+    }
+
+    // Reads a few screens past both edges of the view while waiting for a
+    // key, so the next page move finds its lines already indexed.
+    fn read_ahead(&mut self, rows: usize) {
+        let margin = rows * self.options.readahead;
+        if margin > 0 {
+            let range = self.current_range.extendl(margin).extendr(margin);
+            self.line_reader.read(&range);
+        }
+    }
+
+    fn reposition(
+        &mut self,
+        anchor: Anchor,
+        rows: usize,
+    ) -> Vec<DrawCommand<'a>> {
+        let target = match anchor {
+            Anchor::Top => 0,
+            Anchor::Center => rows / 2,
+            Anchor::Bottom => rows.saturating_sub(1),
+        };
+        let new_range = match self.cursor.checked_sub(target) {
+            Some(by) => self.current_range.shiftr(by),
+            None => self.current_range.shiftl(target - self.cursor),
+        };
+        let read_lines = self.read_filled(new_range);
+        let current_range = self.current_range.clone();
+        let shift = self
+            .distance(&current_range, &read_lines.range)
+            .unwrap_or(self.cursor as isize - target as isize);
+        let last = read_lines.lines.len().saturating_sub(1) as isize;
+        self.cursor = (self.cursor as isize - shift).max(0).min(last) as usize;
+        self.show(read_lines)
+    }
+
+    fn show(&mut self, read_lines: ReadLines<'a>) -> Vec<DrawCommand<'a>> {
         if read_lines.range != self.current_range {
             self.current_range = read_lines.range.clone();
             return self.mk_draw_commands(read_lines);
@@ -62,21 +787,874 @@ impl<'a> NormalMode<'a> {
         vec![]
     }
 
-    fn mk_draw_commands(&self, lines: ReadLines<'a>) -> Vec<DrawCommand<'a>> {
+    // None when one range is counted from the end of a file that isn't
+    // indexed yet, as just after G.
+    fn distance(&self, from: &LinesRange, to: &LinesRange) -> Option<isize> {
+        match (&from.sign, &to.sign) {
+            (Sign::Pos, Sign::Pos) => {
+                Some(to.range.start as isize - from.range.start as isize)
+            }
+            (Sign::Neg, Sign::Neg) => {
+                Some(from.range.end as isize - to.range.end as isize)
+            }
+            _ => Some(
+                self.first_line(to)? as isize - self.first_line(from)? as isize,
+            ),
+        }
+    }
+
+    fn process_hmove(
+        &mut self,
+        hmove: HorizontalMove,
+        cols: usize,
+    ) -> Vec<DrawCommand<'a>> {
+        let step = match self.options.hscroll {
+            0 => cols / 2,
+            step => step,
+        };
+        let old = self.col_offset;
+        let tabs = self.options.tabs;
+        let col_offset = match (hmove, self.options.hsnap) {
+            (HorizontalMove::Left, false) => old.saturating_sub(step),
+            (HorizontalMove::Right, false) => old + step,
+            // At least one tab stop over, however small the step.
+            (HorizontalMove::Left, true) => {
+                let stop = old.saturating_sub(step).div_ceil(tabs) * tabs;
+                match stop < old {
+                    true => stop,
+                    false => old.saturating_sub(1) / tabs * tabs,
+                }
+            }
+            (HorizontalMove::Right, true) => {
+                let stop = (old + step) / tabs * tabs;
+                stop.max(old / tabs * tabs + tabs)
+            }
+        };
+        if col_offset != self.col_offset {
+            self.col_offset = col_offset;
+            return self.redraw();
+        }
+        vec![]
+    }
+
+    fn toggle_line_numbers(&mut self) -> Vec<DrawCommand<'a>> {
+        self.options.line_numbers = match self.options.line_numbers {
+            LineNumbers::Off => LineNumbers::Absolute,
+            _ => LineNumbers::Off,
+        };
+        self.redraw()
+    }
+
+    fn toggle_relative_numbers(&mut self) -> Vec<DrawCommand<'a>> {
+        self.options.line_numbers = match self.options.line_numbers {
+            LineNumbers::Relative => LineNumbers::Absolute,
+            _ => LineNumbers::Relative,
+        };
+        self.redraw()
+    }
+
+    // Keeps the top line in place, or the bottom one when the view is
+    // anchored to the end of the file.
+    fn resize(&mut self, rows: usize) -> Vec<DrawCommand<'a>> {
+        let old_len = self.current_range.range.len();
+        // Once the file is indexed, the last page reads back counted from
+        // the top, but it's still anchored to the end.
+        let range = self.current_range.range.clone();
+        if self.current_range.sign == Sign::Pos && range.start > 0 {
+            let next = LinesRange::pos(range.end..range.end + 1);
+            if self.line_reader.read(&next).range.range.start != range.end {
+                self.current_range = LinesRange::neg(0..old_len);
+            }
+        }
+        let start = self.current_range.range.start;
+        self.current_range.range = start..start + rows;
+        let lines = self.line_reader.read(&self.current_range);
+        let len = lines.lines.len();
+        let cursor = match self.current_range.sign {
+            Sign::Pos => self.cursor,
+            Sign::Neg => {
+                let below = old_len.saturating_sub(self.cursor + 1);
+                len.saturating_sub(below + 1)
+            }
+        };
+        self.current_range = lines.range.clone();
+        self.cursor = cursor.min(len.saturating_sub(1));
+        self.mk_draw_commands(lines)
+    }
+
+    fn redraw(&mut self) -> Vec<DrawCommand<'a>> {
+        let lines = self.line_reader.read(&self.current_range);
+        self.mk_draw_commands(lines)
+    }
+
+    // The first line of `range`, if that's known without indexing more of
+    // the file.
+    fn first_line(&self, range: &LinesRange) -> Option<usize> {
+        match range.sign {
+            Sign::Pos => Some(range.range.start),
+            Sign::Neg => Some(self.line_reader.indexed()? - range.range.end),
+        }
+    }
+
+    // The number of lines, when counting them stays within the budget.
+    fn count(&mut self) -> Option<usize> {
+        let total = self.line_reader.count_lines(self.scan_budget);
+        self.indexing = total.is_none();
+        total
+    }
+
+    // Like first_line, but indexes as far as the budget allows, and then
+    // the rest while idle.
+    fn known_first(&mut self, range: &LinesRange) -> Option<usize> {
+        match range.sign {
+            Sign::Pos => Some(range.range.start),
+            Sign::Neg => Some(self.count()? - range.range.end),
+        }
+    }
+
+    fn show_ruler(&mut self, cols: usize) -> Vec<DrawCommand<'a>> {
+        self.ruler = true;
+        self.message = Some(format!("Width: {} columns", cols));
+        self.redraw()
+    }
+
+    fn toggle_scrollbar(&mut self) -> Vec<DrawCommand<'a>> {
+        self.options.scrollbar = !self.options.scrollbar;
+        self.redraw()
+    }
+
+    fn toggle_theme(&mut self) -> Vec<DrawCommand<'a>> {
+        self.options.theme = match self.options.theme {
+            Theme::Dark => Theme::Light,
+            _ => Theme::Dark,
+        };
+        self.retheme()
+    }
+
+    fn retheme(&mut self) -> Vec<DrawCommand<'a>> {
+        let mut cmds = vec![DrawCommand::SetTheme {
+            theme: self.options.theme,
+        }];
+        cmds.append(&mut self.redraw());
+        cmds
+    }
+
+    fn toggle_center(&mut self) -> Vec<DrawCommand<'a>> {
+        self.options.center = !self.options.center;
+        self.redraw()
+    }
+
+    fn toggle_minimap(&mut self) -> Vec<DrawCommand<'a>> {
+        self.options.minimap = !self.options.minimap;
+        self.redraw()
+    }
+
+    // Built again only when the search it shows changes.
+    fn minimap(&mut self) -> Option<Vec<minimap::Cell>> {
+        if !self.options.minimap {
+            return None;
+        }
+        let pattern = self.pattern.clone().filter(|_| self.hilite);
+        let key = (pattern, self.search_flags());
+        match &self.minimap {
+            Some((built_for, cells)) if *built_for == key => {
+                return Some(cells.clone())
+            }
+            _ => {}
+        }
+        let total = self.count()?;
+        let cells =
+            minimap::build(self.line_reader, total, key.0.as_deref(), key.1);
+        self.minimap = Some((key, cells.clone()));
+        Some(cells)
+    }
+
+    fn scrollbar(&mut self, lines: &ReadLines<'a>) -> Option<Scrollbar> {
+        if !self.options.scrollbar {
+            return None;
+        }
+        Some(Scrollbar {
+            first: self.known_first(&lines.range)?,
+            visible: lines.lines.len(),
+            total: self.count()?,
+        })
+    }
+
+    // The line the cursor is on, for commands that start from it. None
+    // while the lines are still being counted, which the status shows.
+    fn current_line(&mut self) -> Option<usize> {
+        let range = self.current_range.clone();
+        Some(self.known_first(&range)? + self.cursor)
+    }
+
+    fn start_search(&mut self) -> Option<PromptAction> {
+        Some(PromptAction::Search {
+            origin: self.current_range.clone(),
+            cursor: self.cursor,
+            inc: IncSearch::new(
+                self.current_line()?,
+                self.options.search_flags(),
+            ),
+        })
+    }
+
+    fn edit(
+        &mut self,
+        action: &mut PromptAction,
+        input: &str,
+        rows: usize,
+    ) -> Vec<DrawCommand<'a>> {
+        match action {
+            PromptAction::Search {
+                origin,
+                cursor,
+                inc,
+            } => {
+                let found = inc.update(self.line_reader, input);
+                self.current_match = None;
+                match found {
+                    Some(found) => {
+                        self.current_range = origin.clone();
+                        self.cursor = *cursor;
+                        self.jump(found, rows)
+                    }
+                    None => self.restore(origin.clone(), *cursor),
+                }
+            }
+            // Lists the commands that would run, as a palette, with the
+            // selected one marked and kept on screen.
+            PromptAction::Execute { selected } => match palette(input, 0) {
+                Some(_) => {
+                    let found = keymap::find(input);
+                    *selected = (*selected).min(found.len() - 1);
+                    let top = selected.saturating_sub(rows.saturating_sub(1));
+                    let lines = found.into_iter().enumerate().skip(top).map(
+                        |(i, (_, line))| match i == *selected {
+                            true => format!(">{}", &line[1..]),
+                            false => line,
+                        },
+                    );
+                    vec![DrawCommand::DrawOverlay {
+                        lines: Cow::Owned(lines.collect()),
+                    }]
+                }
+                None => self.redraw(),
+            },
+            _ => vec![],
+        }
+    }
+
+    fn cancel(&mut self, action: PromptAction) -> Vec<DrawCommand<'a>> {
+        match action {
+            PromptAction::Search { origin, cursor, .. } => {
+                self.current_match = None;
+                self.restore(origin, cursor)
+            }
+            PromptAction::Execute { .. } => self.redraw(),
+            _ => self.draw_status(),
+        }
+    }
+
+    fn restore(
+        &mut self,
+        range: LinesRange,
+        cursor: usize,
+    ) -> Vec<DrawCommand<'a>> {
+        self.current_range = range;
+        self.cursor = cursor;
+        self.redraw()
+    }
+
+    fn find_next(
+        &mut self,
+        backward: bool,
+        rows: usize,
+    ) -> Vec<DrawCommand<'a>> {
+        let pattern = match &self.pattern {
+            Some(pattern) => pattern.clone(),
+            None => {
+                self.message = Some("No previous search".to_owned());
+                return self.draw_status();
+            }
+        };
+        let line = match self.current_line() {
+            Some(line) => line,
+            None => return self.draw_status(),
+        };
+        let flags = self.search_flags();
+        let found = if backward {
+            search::find_back(self.line_reader, &pattern, flags, line)
+        } else {
+            search::find_forw(self.line_reader, &pattern, flags, line + 1)
+        };
+        match found {
+            Some(found) => self.jump(found, rows),
+            None => {
+                self.message = Some(format!("Pattern not found: {}", pattern));
+                self.draw_status()
+            }
+        }
+    }
+
+    fn search_flags(&self) -> search::Flags {
+        let mut flags = self.options.search_flags();
+        flags.whole_word |= self.word_search;
+        flags
+    }
+
+    fn search_word(
+        &mut self,
+        backward: bool,
+        rows: usize,
+    ) -> Vec<DrawCommand<'a>> {
+        let line = match self.current_line() {
+            Some(line) => line,
+            None => return self.draw_status(),
+        };
+        let col = match &self.current_match {
+            Some((found, m)) if *found == line => m.start,
+            _ => 0,
+        };
+        let read_lines =
+            self.line_reader.read(&LinesRange::pos(line..line + 1));
+        let word = read_lines
+            .lines
+            .first()
+            .and_then(|text| search::word_at(text, col));
+        match word {
+            Some(word) => {
+                self.pattern = Some(word.to_owned());
+                self.word_search = true;
+                self.find_next(backward, rows)
+            }
+            None => {
+                self.message = Some("No word on the current line".to_owned());
+                self.draw_status()
+            }
+        }
+    }
+
+    fn paragraph(
+        &mut self,
+        forward: bool,
+        rows: usize,
+        count: usize,
+    ) -> Vec<DrawCommand<'a>> {
+        let blank = |line: &str| Some(0..0).filter(|_| line.trim().is_empty());
+        let mut line = match self.current_line() {
+            Some(line) => line,
+            None => return self.draw_status(),
+        };
+        for _ in 0..count {
+            let found = if forward {
+                search::scan_forw(self.line_reader, line + 1, blank)
+            } else {
+                search::scan_back(self.line_reader, line, blank)
+            };
+            line = match found {
+                Some((found, _)) => found,
+                None if forward => {
+                    return self.process_move(
+                        VerticalMove::Bottom,
+                        rows,
+                        1,
+                        None,
+                    )
+                }
+                None => 0,
+            };
+        }
+        self.goto_line(line, rows)
+    }
+
+    fn match_bracket(&mut self, rows: usize) -> Vec<DrawCommand<'a>> {
+        let line = match self.current_line() {
+            Some(line) => line,
+            None => return self.draw_status(),
+        };
+        let col = match &self.current_match {
+            Some((found, m)) if *found == line => m.start,
+            _ => 0,
+        };
+        match search::match_bracket(self.line_reader, line, col) {
+            Some(found) => self.jump(found, rows),
+            None => {
+                self.message = Some("No matching bracket".to_owned());
+                self.draw_status()
+            }
+        }
+    }
+
+    fn jump(&mut self, found: Match, rows: usize) -> Vec<DrawCommand<'a>> {
+        let line = found.0;
+        self.hilite = true;
+        self.current_match = Some(found);
+        self.goto_line(line, rows)
+    }
+
+    fn goto_line(&mut self, line: usize, rows: usize) -> Vec<DrawCommand<'a>> {
+        let range = self.current_range.clone();
+        if let Some(first) = self.first_line(&range) {
+            if (first..first + range.range.len()).contains(&line) {
+                self.cursor = line - first;
+                return self.redraw();
+            }
+        }
+        let lines = self.read_filled(LinesRange::pos(line..line + rows));
+        self.cursor = line - lines.range.range.start;
+        self.current_range = lines.range.clone();
+        self.mk_draw_commands(lines)
+    }
+
+    fn highlight(&mut self, lines: &ReadLines<'a>) -> Option<Match> {
+        if !self.hilite {
+            return None;
+        }
+        let (line, m) = self.current_match.clone()?;
+        let row = line.checked_sub(self.first_line(&lines.range)?)?;
+        Some((row, m)).filter(|_| row < lines.lines.len())
+    }
+
+    // What to highlight every match of, found as each line is drawn.
+    fn search(&self) -> Option<(String, search::Flags)> {
+        let pattern = self.pattern.clone().filter(|_| self.hilite)?;
+        Some((pattern, self.search_flags()))
+    }
+
+    fn clear_highlight(&mut self) -> Vec<DrawCommand<'a>> {
+        self.hilite = false;
+        self.redraw()
+    }
+
+    fn numbers(&mut self, lines: &ReadLines<'a>) -> Option<Vec<Option<usize>>> {
+        if self.options.line_numbers == LineNumbers::Off {
+            return None;
+        }
+        let first = self.known_first(&lines.range)?;
+        let numbers: Vec<Option<usize>> = match self.options.line_numbers {
+            LineNumbers::Off => return None,
+            LineNumbers::Absolute => (first..first + lines.lines.len())
+                .map(|line| Some(self.origin(line) + 1))
+                .collect(),
+            LineNumbers::Relative => (0..lines.lines.len())
+                .map(|i| Some(if i > 0 { i } else { self.origin(first) + 1 }))
+                .collect(),
+            LineNumbers::NonBlank => {
+                let mut n = self.nonblank_before(first)?;
+                let number = |line: &Cow<str>| {
+                    n += !line.is_empty() as usize;
+                    Some(n).filter(|_| !line.is_empty())
+                };
+                lines.lines.iter().map(number).collect()
+            }
+        };
+        let continued = self.continued(lines);
+        let numbers = numbers
+            .into_iter()
+            .zip(continued.iter().chain(iter::repeat(&false)))
+            .map(|(n, &continued)| n.filter(|_| !continued))
+            .collect();
+        Some(numbers)
+    }
+
+    // The line of the file `line` stands for, when narrowed or squeezed.
+    fn origin(&mut self, line: usize) -> usize {
+        let start = match &mut self.full {
+            Some(full) => full.origin(self.offset),
+            None => self.offset,
+        };
+        start + self.line_reader.origin(line)
+    }
+
+    // The rows that carry on the line before them, when grouping them.
+    fn continued(&self, lines: &ReadLines<'a>) -> Vec<bool> {
+        if !self.options.group {
+            return vec![];
+        }
+        let continues = |line: &Cow<str>| self.options.continues(line);
+        lines.lines.iter().map(continues).collect()
+    }
+
+    // Counts from the previous answer, so paging through the file doesn't
+    // rescan it from the top each time. No more than the budget of lines at
+    // a time: None until it gets there, counting on while idle.
+    fn nonblank_before(&mut self, line: usize) -> Option<usize> {
+        let (cached, count) = self.nonblank;
+        let budget = self.scan_budget.max(1);
+        self.nonblank = if line >= cached {
+            let to = line.min(cached + budget);
+            (to, count + self.count_nonblank(cached..to))
+        } else {
+            let to = line.max(cached - budget.min(cached));
+            (to, count - self.count_nonblank(to..cached))
+        };
+        let (counted, count) = self.nonblank;
+        self.indexing |= counted != line;
+        Some(count).filter(|_| counted == line)
+    }
+
+    fn count_nonblank(&mut self, range: Range<usize>) -> usize {
+        const CHUNK: usize = 4096;
+        (range.start..range.end)
+            .step_by(CHUNK)
+            .map(|start| {
+                let end = (start + CHUNK).min(range.end);
+                let lines = self.line_reader.read(&LinesRange::pos(start..end));
+                lines.lines.iter().filter(|line| !line.is_empty()).count()
+            })
+            .sum()
+    }
+
+    fn mk_draw_commands(
+        &mut self,
+        lines: ReadLines<'a>,
+    ) -> Vec<DrawCommand<'a>> {
+        let mut cmds = vec![self.draw_content(lines)];
+        cmds.append(&mut self.draw_status());
+        cmds
+    }
+
+    fn draw_content(&mut self, lines: ReadLines<'a>) -> DrawCommand<'a> {
+        let numbers = self.numbers(&lines);
+        let highlight = self.highlight(&lines);
+        let search = self.search();
+        let scrollbar = self.scrollbar(&lines);
+        let minimap = self.minimap();
+        let continued = self.continued(&lines);
+        DrawCommand::DrawContent {
+            lines,
+            col_offset: self.col_offset,
+            numbers,
+            cursor: self.cursor,
+            filler: self.filler,
+            patterns: self.patterns,
+            format: self.options.format(),
+            center: self.options.center && self.col_offset == 0,
+            ruler: std::mem::take(&mut self.ruler),
+            scrollbar,
+            minimap,
+            continued,
+            highlight,
+            search,
+        }
+    }
+
+    fn draw_status(&mut self) -> Vec<DrawCommand<'a>> {
+        vec![DrawCommand::DrawStatus {
+            status: self.status(),
+        }]
+    }
+
+    fn notify(&mut self, message: &str) -> Vec<DrawCommand<'a>> {
+        self.message = Some(message.to_owned());
+        self.draw_status()
+    }
+
+    fn status(&mut self) -> Cow<'a, str> {
+        match self.message.take() {
+            Some(message) => Cow::Owned(message),
+            None if self.indexing => Cow::Owned(format!(
+                "{} (still indexing, press a key to continue or wait)",
+                self.line_reader.filename
+            )),
+            None if self.full.is_some() => {
+                let first = self.offset + 1;
+                let name = self.line_reader.filename;
+                Cow::Owned(match self.count() {
+                    Some(total) => {
+                        let last = self.offset + total;
+                        format!("{} (lines {}-{})", name, first, last)
+                    }
+                    None => format!("{} (from line {})", name, first),
+                })
+            }
+            None => Cow::Borrowed(self.line_reader.filename),
+        }
+    }
+
+    fn submit(
+        &mut self,
+        action: PromptAction,
+        input: String,
+        rows: usize,
+    ) -> (Option<(PromptMode, PromptAction)>, Vec<DrawCommand<'a>>) {
+        let (scope, path, overwrite) = match action {
+            PromptAction::Search { .. } if input.is_empty() => {
+                return (None, self.find_next(false, rows));
+            }
+            PromptAction::Search { .. } => {
+                if self.current_match.is_none() {
+                    let message = format!("Pattern not found: {}", input);
+                    self.message = Some(message);
+                }
+                self.pattern = Some(input);
+                self.word_search = false;
+                return (None, self.draw_status());
+            }
+            PromptAction::Execute { .. } => {
+                return (None, self.execute(&input, rows))
+            }
+            PromptAction::Filter => return (None, self.filter(&input)),
+            PromptAction::PipeLine => return (None, self.pipe_line(&input)),
+            PromptAction::GotoTime => {
+                return (None, self.goto_time(&input, rows))
+            }
+            PromptAction::Save(scope) => (scope, input, false),
+            PromptAction::Overwrite(scope, path) if input == "y" => {
+                (scope, path, true)
+            }
+            PromptAction::Overwrite(..) => return (None, self.draw_status()),
+        };
+        if path.is_empty() {
+            return (None, self.draw_status());
+        }
+        self.message = Some(match self.save(scope, &path, overwrite) {
+            Ok(written) => format!("Wrote {} to {}", written, path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let question = format!("{} exists, overwrite? (y/n) ", path);
+                let next = PromptAction::Overwrite(scope, path);
+                return (Some((PromptMode::new(&question), next)), vec![]);
+            }
+            Err(e) => format!("Can't write {}: {}", path, e),
+        });
+        (None, self.draw_status())
+    }
+
+    fn execute(&mut self, line: &str, rows: usize) -> Vec<DrawCommand<'a>> {
+        let line = line.trim();
+        let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+        let result = match cmd {
+            "" => Ok(()),
+            "set" | "se" => self.options.set(args),
+            "range" => match self.narrow(args, rows) {
+                Ok(cmds) => return cmds,
+                Err(e) => Err(e),
+            },
+            _ => Err(format!("not a command: {}", cmd)),
+        };
+        match result {
+            Ok(()) => self.retheme(),
+            Err(e) => {
+                self.message = Some(e);
+                self.draw_status()
+            }
+        }
+    }
+
+    // `:range START END` shows only those lines, as if they were the whole
+    // file, until `:range` on its own brings the rest back.
+    fn narrow(
+        &mut self,
+        args: &str,
+        rows: usize,
+    ) -> Result<Vec<DrawCommand<'a>>, String> {
+        let bounds: Vec<&str> = args.split_whitespace().collect();
+        let range = self.current_range.clone();
+        let line = self
+            .first_line(&range)
+            .map(|first| first + self.cursor + self.offset);
+        let (start, end) = match bounds[..] {
+            [] if self.full.is_none() => return Ok(self.draw_status()),
+            [] => {
+                self.widen();
+                return Ok(match line {
+                    Some(line) => self.goto_line(line, rows),
+                    None => self.redraw(),
+                });
+            }
+            [start, end] => {
+                let parse =
+                    |n: &str| n.parse::<usize>().ok().filter(|&n| n > 0);
+                match (parse(start), parse(end)) {
+                    (Some(start), Some(end)) if start <= end => (start, end),
+                    _ => return Err(format!("invalid range: {}", args)),
+                }
+            }
+            _ => return Err(String::from("usage: range [START END]")),
+        };
+        self.widen();
+        let first = self.line_reader.read(&LinesRange::pos(start - 1..start));
+        // A read past the end is clamped to the last line, so it ends at
+        // the count.
+        if first.lines.is_empty() || first.range.range.start != start - 1 {
+            return Err(format!("only {} lines", first.range.range.end));
+        }
+        let last = self.line_reader.read(&LinesRange::pos(end - 1..end));
+        let buf = self.line_reader.buf();
+        let bytes = first.buf_range.start.wrapping_add(1)
+            ..(last.buf_range.end + 1).min(buf.len());
+        let mut part = LineReader::new(&buf[bytes], self.line_reader.filename);
+        part.charset = self.line_reader.charset;
+        part.squeeze = self.line_reader.squeeze;
+        self.full = Some(std::mem::replace(self.line_reader, part));
+        self.offset = start - 1;
+        let line = line.unwrap_or(self.offset).saturating_sub(self.offset);
+        self.current_range = LinesRange::pos(0..rows);
+        Ok(self.goto_line(line.min(end - start), rows))
+    }
+
+    // Back to the whole file, with the view where it was.
+    fn widen(&mut self) {
+        if let Some(full) = self.full.take() {
+            // The top of the part, if where in it the view is isn't known.
+            let range = self.current_range.clone();
+            let top = self.first_line(&range).unwrap_or(0) + self.offset;
+            let len = self.current_range.range.len();
+            *self.line_reader = full;
+            self.current_range = LinesRange::pos(top..top + len);
+            self.offset = 0;
+        }
+        self.current_match = None;
+        self.minimap = None;
+        self.nonblank = (0, 0);
+    }
+
+    fn hex_dump(&mut self) -> Vec<DrawCommand<'a>> {
+        let line = match self.current_line() {
+            Some(line) => line,
+            None => return self.draw_status(),
+        };
+        let read_lines =
+            self.line_reader.read(&LinesRange::pos(line..line + 1));
+        let range = read_lines.buf_range;
+        let bytes =
+            &self.line_reader.buf()[range.start.wrapping_add(1)..range.end];
+        // Just the first line of a squeezed run.
+        let bytes = bytes.split(|&b| b == b'\n').next().unwrap_or(bytes);
+        let mut lines = vec![format!(
+            "Line {}, {} bytes",
+            self.origin(line) + 1,
+            bytes.len()
+        )];
+        lines.extend(bytes.chunks(16).enumerate().map(|(i, chunk)| {
+            let hex: Vec<String> =
+                chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let text: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("{:08x}  {:<47}  {}", i * 16, hex.join(" "), text)
+        }));
         vec![
-            DrawCommand::DrawContent { lines: lines },
+            DrawCommand::DrawOverlay {
+                lines: Cow::Owned(lines),
+            },
             DrawCommand::DrawStatus {
-                status: &self.line_reader.filename,
+                status: Cow::Borrowed("Press any key to return"),
             },
         ]
     }
 
-    fn move_and_read(
+    fn goto_time(&mut self, input: &str, rows: usize) -> Vec<DrawCommand<'a>> {
+        let input = input.trim();
+        if input.is_empty() {
+            return self.draw_status();
+        }
+        let buf = self.line_reader.buf();
+        let found = timestamp::default(input)
+            .ok_or_else(|| format!("Not a timestamp: {}", input))
+            .and_then(|target| {
+                timestamp::find(buf, &target, timestamp::default)
+                    .ok_or_else(|| format!("Nothing at or after {}", input))
+            });
+        match found {
+            Ok(line) => {
+                let line = self.line_reader.squeezed(line);
+                self.goto_line(line, rows)
+            }
+            Err(e) => {
+                self.message = Some(e);
+                self.draw_status()
+            }
+        }
+    }
+
+    fn filter(&mut self, cmd: &str) -> Vec<DrawCommand<'a>> {
+        if cmd.trim().is_empty() {
+            return self.draw_status();
+        }
+        let lines = self.line_reader.read(&self.current_range).lines;
+        let mut input = lines.join("\n").into_bytes();
+        input.push(b'\n');
+        self.pipe(cmd, input)
+    }
+
+    fn pipe_line(&mut self, cmd: &str) -> Vec<DrawCommand<'a>> {
+        if cmd.trim().is_empty() {
+            return self.draw_status();
+        }
+        let line = match self.current_line() {
+            Some(line) => line,
+            None => return self.draw_status(),
+        };
+        let lines = self.line_reader.read(&LinesRange::pos(line..line + 1));
+        let mut input = lines.lines.concat().into_bytes();
+        input.push(b'\n');
+        self.pipe(cmd, input)
+    }
+
+    // A single line of output fits in the status line, more gets an overlay.
+    fn pipe(&mut self, cmd: &str, input: Vec<u8>) -> Vec<DrawCommand<'a>> {
+        match filter::text(cmd, input) {
+            Ok(output) => {
+                let output = String::from_utf8_lossy(&output);
+                if output.trim_end_matches('\n').lines().count() == 1 {
+                    return self.notify(output.trim_end());
+                }
+                vec![
+                    DrawCommand::DrawOverlay {
+                        lines: Cow::Owned(
+                            output.lines().map(str::to_owned).collect(),
+                        ),
+                    },
+                    DrawCommand::DrawStatus {
+                        status: Cow::Borrowed("Press any key to return"),
+                    },
+                ]
+            }
+            Err(e) => self.notify(&e),
+        }
+    }
+
+    fn save(
         &mut self,
-        vmove: VerticalMove,
-        rows: usize,
-    ) -> ReadLines<'a> {
-        let new_range = mv(vmove, self.current_range.clone(), rows);
+        scope: SaveScope,
+        path: &str,
+        overwrite: bool,
+    ) -> io::Result<String> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(overwrite)
+            .create_new(!overwrite)
+            .open(path)?;
+        match scope {
+            SaveScope::Screen => {
+                let lines = self.line_reader.read(&self.current_range).lines;
+                for line in &lines {
+                    writeln!(file, "{}", line)?;
+                }
+                Ok(format!("{} lines", lines.len()))
+            }
+            // In bytes when counting the lines would take too long.
+            SaveScope::File => {
+                let buf = self.line_reader.buf();
+                file.write_all(buf)?;
+                Ok(match self.line_reader.count_lines(self.scan_budget) {
+                    Some(lines) => format!("{} lines", lines),
+                    None => format!("{} bytes", buf.len()),
+                })
+            }
+        }
+    }
+
+    fn read_filled(&mut self, new_range: LinesRange) -> ReadLines<'a> {
         let requested_nr = new_range.range.size_hint().0;
         let lines = self.line_reader.read(&new_range);
         match requested_nr - lines.lines.len() {
@@ -86,38 +1664,83 @@ impl<'a> NormalMode<'a> {
     }
 }
 
+// The search a minimap was built for.
+type MapKey = (Option<String>, search::Flags);
+
+struct Scrollbar {
+    first: usize,
+    visible: usize,
+    total: usize,
+}
+
+#[allow(clippy::enum_variant_names, clippy::large_enum_variant)]
 enum DrawCommand<'a> {
-    DrawContent { lines: ReadLines<'a> },
-    DrawStatus { status: &'a str },
+    DrawContent {
+        lines: ReadLines<'a>,
+        col_offset: usize,
+        numbers: Option<Vec<Option<usize>>>,
+        cursor: usize,
+        filler: &'a str,
+        patterns: &'a [Highlight],
+        format: Format,
+        center: bool,
+        ruler: bool,
+        scrollbar: Option<Scrollbar>,
+        minimap: Option<Vec<minimap::Cell>>,
+        continued: Vec<bool>,
+        highlight: Option<Match>,
+        search: Option<(String, search::Flags)>,
+    },
+    DrawStatus {
+        status: Cow<'a, str>,
+    },
+    DrawOverlay {
+        lines: Cow<'a, [String]>,
+    },
+    SetTheme {
+        theme: Theme,
+    },
 }
 
 fn mv(
     mv: VerticalMove,
     current_line_range: LinesRange,
     rows: usize,
+    count: usize,
 ) -> LinesRange {
     match mv {
         VerticalMove::Top => LinesRange::pos(0..rows),
         VerticalMove::Bottom => LinesRange::neg(0..rows),
-        VerticalMove::LineUp => current_line_range.shiftl(1),
-        VerticalMove::LineDown => current_line_range.shiftr(1),
-        VerticalMove::PageUp => current_line_range.shiftl(rows),
-        VerticalMove::PageDown => current_line_range.shiftr(rows),
-        VerticalMove::HalfPageUp => current_line_range.shiftl(rows / 2),
-        VerticalMove::HalfPageDown => current_line_range.shiftr(rows / 2),
+        VerticalMove::LineUp | VerticalMove::HalfPageUp => {
+            current_line_range.shiftl(count)
+        }
+        VerticalMove::LineDown | VerticalMove::HalfPageDown => {
+            current_line_range.shiftr(count)
+        }
+        VerticalMove::PageUp => current_line_range.shiftl(rows * count),
+        VerticalMove::PageDown => current_line_range.shiftr(rows * count),
+        VerticalMove::ParagraphUp | VerticalMove::ParagraphDown => {
+            current_line_range
+        }
     }
 }
 
 fn draw<'a, S: Screen, I>(screen: &'a mut S, cmds: I) -> Result<(), DrawError>
 where
     I: IntoIterator<Item = DrawCommand<'a>>, {
-    cmds.into_iter().map(|cmd| screen.draw(cmd)).collect()
+    cmds.into_iter().try_for_each(|cmd| screen.draw(cmd))
+}
+
+fn gutter_width(numbers: &[Option<usize>]) -> usize {
+    let max = numbers.iter().flatten().max().copied().unwrap_or(0);
+    max.to_string().len().max(3) + 1
 }
 
 trait Screen {
     fn rows(&self) -> usize;
     fn cols(&self) -> usize;
     fn draw<'a>(&'a mut self, cmd: DrawCommand<'a>) -> Result<(), DrawError>;
+    fn resize(&mut self, cols: u16, rows: u16);
     fn cleanup(&mut self);
 }
 
@@ -125,6 +1748,28 @@ struct ConsoleScreen {
     rows: u16,
     cols: u16,
     out: Stdout,
+    alternate: bool,
+    colors: style::Colors,
+    depth: Depth,
+    status: bool,
+}
+
+// Rows for the file and whether there's a status line. On a one-row
+// terminal the status line is dropped so a line of the file still shows.
+fn layout(rows: u16) -> (u16, bool) {
+    match rows {
+        0 | 1 => (1, false),
+        rows => (rows - 1, true),
+    }
+}
+
+fn colors(theme: Theme, depth: Depth) -> style::Colors {
+    let (fg, bg) = match theme {
+        Theme::Terminal => (style::Color::Reset, style::Color::Reset),
+        Theme::Dark => (style::Color::Grey, style::Color::Black),
+        Theme::Light => (style::Color::Black, style::Color::White),
+    };
+    style::Colors::new(palette::fit(fg, depth), palette::fit(bg, depth))
 }
 
 #[derive(Debug)]
@@ -138,57 +1783,379 @@ impl From<io::Error> for DrawError {
     }
 }
 
-impl From<crossterm::ErrorKind> for DrawError {
-    fn from(e: crossterm::ErrorKind) -> Self {
-        match e {
-            crossterm::ErrorKind::IoError(_ioerr) => DrawError::SomeError,
-            crossterm::ErrorKind::FmtError(_fmterr) => DrawError::SomeError,
-            crossterm::ErrorKind::Utf8Error(_utferr) => DrawError::SomeError,
-            crossterm::ErrorKind::ParseIntError(_parseerr) => {
-                DrawError::SomeError
-            }
-            crossterm::ErrorKind::ResizingTerminalFailure(_msg) => {
-                DrawError::SomeError
-            }
-            _ => DrawError::SomeError,
-        }
-    }
-}
-
 impl ConsoleScreen {
-    fn init() -> Result<Self, Box<dyn std::error::Error>> {
+    fn init(
+        alternate: bool,
+        theme: Theme,
+        depth: Depth,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         terminal::enable_raw_mode()?;
+        if alternate {
+            execute!(stdout(), terminal::EnterAlternateScreen)?;
+        }
+        execute!(stdout(), event::EnableBracketedPaste)?;
+        restore_on_signal(alternate)?;
         let (cols, rows) = terminal::size()?;
+        let (rows, status) = layout(rows);
         Ok(ConsoleScreen {
-            rows: rows - 1,
+            rows,
+            status,
             cols,
             out: stdout(),
+            alternate,
+            colors: colors(theme, depth),
+            depth,
         })
     }
 }
 
+impl ConsoleScreen {
+    fn draw_line(
+        &mut self,
+        shown: &Rendered,
+        patterns: &[Highlight],
+        width: usize,
+        indent: usize,
+        selected: bool,
+    ) -> Result<(), DrawError> {
+        if selected {
+            queue!(self.out, style::SetAttribute(style::Attribute::Reverse))?;
+        }
+        queue!(self.out, style::Print(" ".repeat(indent)))?;
+        let colors = self.colors;
+        let depth = self.depth;
+        let mut printed = 0;
+        for (range, mark) in &shown.marks {
+            queue!(self.out, style::Print(&shown.text[printed..range.start]))?;
+            match mark {
+                // Without colors, matches are told apart by underlining.
+                Mark::Current | Mark::Match if depth == Depth::None => {
+                    let bold = match mark {
+                        Mark::Current => style::Attribute::Bold,
+                        _ => style::Attribute::NoBold,
+                    };
+                    queue!(
+                        self.out,
+                        style::SetAttribute(style::Attribute::Underlined),
+                        style::SetAttribute(bold),
+                        style::Print(&shown.text[range.clone()]),
+                        style::SetAttribute(style::Attribute::NoUnderline),
+                        style::SetAttribute(style::Attribute::NormalIntensity)
+                    )?
+                }
+                Mark::Current => queue!(
+                    self.out,
+                    style::SetForegroundColor(style::Color::Black),
+                    style::SetBackgroundColor(style::Color::Yellow),
+                    style::Print(&shown.text[range.clone()]),
+                    style::SetColors(colors)
+                )?,
+                Mark::Match => queue!(
+                    self.out,
+                    style::SetForegroundColor(style::Color::Black),
+                    style::SetBackgroundColor(style::Color::DarkYellow),
+                    style::Print(&shown.text[range.clone()]),
+                    style::SetColors(colors)
+                )?,
+                Mark::Pattern(n) => queue!(
+                    self.out,
+                    style::SetForegroundColor(palette::fit(
+                        patterns[*n].color,
+                        depth
+                    )),
+                    style::Print(&shown.text[range.clone()]),
+                    style::SetColors(colors)
+                )?,
+                Mark::Link(url) => queue!(
+                    self.out,
+                    style::Print(render::hyperlink(
+                        url,
+                        &shown.text[range.clone()]
+                    ))
+                )?,
+            }
+            printed = range.end;
+        }
+        queue!(self.out, style::Print(&shown.text[printed..]))?;
+        if selected {
+            let shown_width = indent + render::columns(&shown.text);
+            let pad = width.saturating_sub(shown_width);
+            queue!(self.out, style::Print(" ".repeat(pad)))?;
+        }
+        // Colors from a raw line mustn't run on into the rest of the screen.
+        if selected || shown.text.contains('\x1b') {
+            self.reset_style()?;
+        }
+        Ok(())
+    }
+
+    fn reset_style(&mut self) -> Result<(), DrawError> {
+        queue!(
+            self.out,
+            style::SetAttribute(style::Attribute::Reset),
+            style::SetColors(self.colors)
+        )?;
+        Ok(())
+    }
+
+    fn draw_scrollbar(
+        &mut self,
+        bar: Scrollbar,
+        col: u16,
+    ) -> Result<(), DrawError> {
+        let rows = self.rows();
+        let total = bar.total.max(1);
+        let len = (bar.visible * rows).div_ceil(total).clamp(1, rows);
+        let start = (bar.first * rows / total).min(rows - len);
+        for i in 0..rows {
+            let cell = if (start..start + len).contains(&i) {
+                "█"
+            } else {
+                "│"
+            };
+            queue!(
+                self.out,
+                cursor::MoveTo(col, i as u16),
+                style::Print(cell)
+            )?;
+        }
+        Ok(())
+    }
+
+    // Each row stands for an even share of the file: the shade is how long
+    // its lines are, and a marked row has a match of the current search.
+    fn draw_minimap(
+        &mut self,
+        cells: &[minimap::Cell],
+    ) -> Result<(), DrawError> {
+        let rows = self.rows();
+        let col = self.cols().saturating_sub(minimap::WIDTH) as u16;
+        for i in 0..rows {
+            let start = i * cells.len() / rows;
+            let end = ((i + 1) * cells.len() / rows).max(start + 1);
+            let cell =
+                minimap::Cell::merge(&cells[start..end.min(cells.len())]);
+            let shade = cell.shade();
+            queue!(
+                self.out,
+                cursor::MoveTo(col, i as u16),
+                style::Print(shade)
+            )?;
+            if cell.matched {
+                let color = palette::fit(style::Color::Yellow, self.depth);
+                queue!(
+                    self.out,
+                    style::SetForegroundColor(color),
+                    style::Print('•'),
+                    style::SetColors(self.colors)
+                )?;
+            } else {
+                queue!(self.out, style::Print(shade))?;
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Screen for ConsoleScreen {
     fn draw<'a>(&'a mut self, cmd: DrawCommand<'a>) -> Result<(), DrawError> {
         match cmd {
-            DrawCommand::DrawContent { lines } => {
+            DrawCommand::DrawContent {
+                lines,
+                col_offset,
+                numbers,
+                cursor,
+                filler,
+                patterns,
+                format,
+                center,
+                ruler,
+                scrollbar,
+                minimap,
+                continued,
+                highlight,
+                search,
+            } => {
+                self.reset_style()?;
                 queue!(self.out, terminal::Clear(terminal::ClearType::All))?;
+                let gutter = numbers.as_ref().map_or(0, |n| gutter_width(n));
+                let map = minimap.as_ref().map_or(0, |_| minimap::WIDTH);
+                let bar = scrollbar.as_ref().map_or(0, |_| 1);
+                let width = self.cols().saturating_sub(gutter + bar + map);
                 let lines_to_draw = lines.lines.iter().take(self.rows as usize);
                 for (i, line) in lines_to_draw.enumerate() {
+                    queue!(self.out, cursor::MoveTo(0, i as u16))?;
+                    if let Some(n) = numbers.as_ref().and_then(|n| n.get(i)) {
+                        let number = match n {
+                            Some(n) => format!("{:>w$} ", n, w = gutter - 1),
+                            None => " ".repeat(gutter),
+                        };
+                        queue!(self.out, style::Print(number))?;
+                    }
+                    let mut marks: Vec<_> = highlight
+                        .as_ref()
+                        .filter(|(row, _)| *row == i)
+                        .map(|(_, m)| (m.clone(), Mark::Current))
+                        .into_iter()
+                        .collect();
+                    // Only what shows of the line is looked for matches and
+                    // links, however long the line.
+                    let span = render::span(line, col_offset, width, format);
+                    if let Some((pattern, flags)) = &search {
+                        let found = search::find_all_in_span(
+                            line,
+                            pattern,
+                            *flags,
+                            span.clone(),
+                        );
+                        marks.extend(
+                            found.into_iter().map(|m| (m, Mark::Match)),
+                        );
+                    }
+                    for (n, highlight) in patterns.iter().enumerate() {
+                        let found = search::find_all_in_span(
+                            line,
+                            &highlight.pattern,
+                            search::Flags::default(),
+                            span.clone(),
+                        );
+                        marks.extend(
+                            found.into_iter().map(|r| (r, Mark::Pattern(n))),
+                        );
+                    }
+                    if format.links {
+                        let links = render::links(line, span.clone());
+                        marks.extend(links.into_iter().map(|r| {
+                            let url = line[r.clone()].to_owned();
+                            (r, Mark::Link(url))
+                        }));
+                    }
+                    let shown =
+                        render::render(line, col_offset, width, format, &marks);
+                    let indent = match center {
+                        true => {
+                            width.saturating_sub(render::columns(&shown.text))
+                                / 2
+                        }
+                        false => 0,
+                    };
+                    let dim = continued.get(i) == Some(&true);
+                    if dim {
+                        queue!(
+                            self.out,
+                            style::SetAttribute(style::Attribute::Dim)
+                        )?;
+                    }
+                    self.draw_line(
+                        &shown,
+                        patterns,
+                        width,
+                        indent,
+                        i == cursor,
+                    )?;
+                    if dim {
+                        self.reset_style()?;
+                    }
+                    if let Some(&n) = lines.repeats.get(i).filter(|&&n| n > 1) {
+                        let count = format!(" (x{})", n);
+                        let end = indent + render::columns(&shown.text);
+                        let col = end.min(width.saturating_sub(count.len()));
+                        queue!(
+                            self.out,
+                            cursor::MoveTo((gutter + col) as u16, i as u16),
+                            style::SetAttribute(style::Attribute::Dim),
+                            style::Print(count)
+                        )?;
+                        self.reset_style()?;
+                    }
+                    if shown.cut_left {
+                        queue!(
+                            self.out,
+                            cursor::MoveTo(gutter as u16, i as u16),
+                            style::Print('<')
+                        )?;
+                    }
+                    if shown.cut_right && width > 0 {
+                        let last = gutter + width - 1;
+                        queue!(
+                            self.out,
+                            cursor::MoveTo(last as u16, i as u16),
+                            style::Print('>')
+                        )?;
+                    }
+                }
+                for i in lines.lines.len()..self.rows as usize {
                     queue!(
                         self.out,
                         cursor::MoveTo(0, i as u16),
-                        style::Print(line)
+                        style::SetAttribute(style::Attribute::Dim),
+                        style::Print(filler)
+                    )?;
+                    self.reset_style()?;
+                }
+                if let Some(scrollbar) = scrollbar {
+                    let col = self.cols().saturating_sub(map + 1);
+                    self.draw_scrollbar(scrollbar, col as u16)?;
+                }
+                if let Some(cells) = minimap {
+                    self.draw_minimap(&cells)?;
+                }
+                if ruler {
+                    queue!(
+                        self.out,
+                        cursor::MoveTo(gutter as u16, 0),
+                        style::SetAttribute(style::Attribute::Reverse),
+                        style::Print(render::ruler(col_offset, width))
                     )?;
+                    self.reset_style()?;
                 }
             }
+            DrawCommand::DrawOverlay { lines } => {
+                self.reset_style()?;
+                queue!(self.out, terminal::Clear(terminal::ClearType::All))?;
+                let format = Format {
+                    tabs: render::TAB_WIDTH,
+                    raw: false,
+                    links: false,
+                };
+                let lines_to_draw = lines.iter().take(self.rows as usize);
+                for (i, line) in lines_to_draw.enumerate() {
+                    let shown =
+                        render::render(line, 0, self.cols(), format, &[]);
+                    queue!(
+                        self.out,
+                        cursor::MoveTo(0, i as u16),
+                        style::Print(shown.text)
+                    )?;
+                }
+            }
+            DrawCommand::DrawStatus { .. } if !self.status => {}
             DrawCommand::DrawStatus { status } => {
+                // Padded out to the full width so the bar spans the line.
+                let format = Format {
+                    tabs: render::TAB_WIDTH,
+                    raw: false,
+                    links: false,
+                };
+                let shown =
+                    render::render(&status, 0, self.cols(), format, &[]);
+                let pad =
+                    self.cols().saturating_sub(render::columns(&shown.text));
                 queue!(
                     self.out,
+                    cursor::SavePosition,
                     cursor::MoveTo(0, self.rows),
+                    style::SetColors(self.colors),
+                    terminal::Clear(terminal::ClearType::CurrentLine),
                     style::SetAttribute(style::Attribute::Reverse),
-                    style::Print(&status),
-                    style::ResetColor
+                    style::Print(shown.text),
+                    style::Print(" ".repeat(pad))
                 )?;
+                self.reset_style()?;
+                queue!(self.out, cursor::RestorePosition)?;
+            }
+            DrawCommand::SetTheme { theme } => {
+                self.colors = colors(theme, self.depth);
             }
         };
         self.out.flush()?;
@@ -203,13 +2170,43 @@ impl Screen for ConsoleScreen {
         self.cols as usize
     }
 
+    fn resize(&mut self, cols: u16, rows: u16) {
+        self.cols = cols;
+        (self.rows, self.status) = layout(rows);
+    }
+
     fn cleanup(&mut self) {
-        queue!(self.out, terminal::Clear(terminal::ClearType::All))
-            .expect("Can't clear terminal");
-        terminal::disable_raw_mode().expect("Terminal problem");
+        restore(self.alternate).expect("Terminal problem");
     }
 }
 
+fn restore(alternate: bool) -> io::Result<()> {
+    let mut out = stdout();
+    queue!(out, style::ResetColor)?;
+    if alternate {
+        queue!(out, terminal::LeaveAlternateScreen)?;
+    } else {
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+    }
+    execute!(out, event::DisableBracketedPaste)?;
+    terminal::disable_raw_mode()
+}
+
+// Being killed, or losing the terminal, skips the cleanup on quitting and
+// would leave the shell in raw mode. The signals are waited for on a thread
+// of their own, where writing to the terminal is safe.
+fn restore_on_signal(alternate: bool) -> io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGHUP, SIGQUIT])?;
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            let _ = restore(alternate);
+            process::exit(128 + signal);
+        }
+    });
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq)]
 enum VerticalMove {
     Bottom,
     HalfPageDown,
@@ -218,65 +2215,328 @@ enum VerticalMove {
     LineUp,
     PageDown,
     PageUp,
+    ParagraphDown,
+    ParagraphUp,
+    Top,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Anchor {
     Top,
+    Center,
+    Bottom,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SaveScope {
+    Screen,
+    File,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum HorizontalMove {
     Left,
     Right,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum Command {
     Quit,
+    Help,
+    ToggleLineNumbers,
+    ToggleRelativeNumbers,
     V(VerticalMove),
     H(HorizontalMove),
+    Reposition(Anchor),
+    Save(SaveScope),
+    Ruler,
+    Execute,
+    Filter,
+    ToggleScrollbar,
+    ToggleCenter,
+    ToggleMinimap,
+    ToggleTheme,
+    Search,
+    ClearHighlight,
+    Repeat,
+    MatchBracket,
+    SearchNext,
+    SearchPrev,
+    SearchWord,
+    SearchWordBack,
+    GotoTime,
+    PipeLine,
+    HexDump,
 }
 
-fn parse<I: Iterator<Item = Event>>(
-    events: I,
-) -> impl Iterator<Item = Command> {
-    events.flat_map(|evt| match evt {
-        Key(KeyEvent {
-            code: Char('q'), ..
-        }) => Some(Command::Quit),
-        Key(KeyEvent {
-            code: Char('j'), ..
-        }) => Some(Command::V(VerticalMove::LineDown)),
-        Key(KeyEvent { code: Up, .. }) => {
-            Some(Command::V(VerticalMove::LineUp))
-        }
-        Key(KeyEvent { code: Down, .. }) => {
-            Some(Command::V(VerticalMove::LineDown))
-        }
-        Key(KeyEvent {
-            code: Char('k'), ..
-        }) => Some(Command::V(VerticalMove::LineUp)),
-        Key(KeyEvent {
-            code: Char('d'),
-            modifiers: KeyModifiers::CONTROL,
-        }) => Some(Command::V(VerticalMove::HalfPageDown)),
-        Key(KeyEvent {
-            code: Char('u'),
-            modifiers: KeyModifiers::CONTROL,
-        }) => Some(Command::V(VerticalMove::HalfPageUp)),
-        Key(KeyEvent { code: PageDown, .. }) => {
-            Some(Command::V(VerticalMove::PageDown))
-        }
-        Key(KeyEvent { code: PageUp, .. }) => {
-            Some(Command::V(VerticalMove::PageUp))
-        }
-        Key(KeyEvent {
-            code: Char('G'), ..
-        }) => Some(Command::V(VerticalMove::Bottom)),
-        Key(KeyEvent {
-            code: Char('g'), ..
-        }) => Some(Command::V(VerticalMove::Top)),
-        Key(KeyEvent { code: Left, .. }) => {
-            Some(Command::H(HorizontalMove::Left))
-        }
-        Key(KeyEvent { code: Right, .. }) => {
-            Some(Command::H(HorizontalMove::Right))
-        }
-        _ => None,
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn end_of_a_short_file_shows_every_line() {
+        let rows = 10;
+        for text in ["", "a", "a\n", "a\nb\nc", "a\nb\nc\n"] {
+            let mut reader = LineReader::new(text.as_bytes(), "test");
+            let (lines, cursor) = first_page(&mut reader, true, 0, rows);
+            let mut reader = LineReader::new(text.as_bytes(), "test");
+            let (expected, _) = first_page(&mut reader, false, 0, rows);
+            // The same lines as opening at the top, from the top row down:
+            // no blank rows above them.
+            assert_eq!(lines.lines, expected.lines, "{:?}", text);
+            assert_eq!(cursor, expected.lines.len() - 1);
+        }
+    }
+
+    // What the draw commands would have put on a terminal, kept in the
+    // order they came in.
+    #[derive(Debug, PartialEq)]
+    enum Drawn {
+        Content { lines: Vec<String>, cursor: usize },
+        Status(String),
+        Overlay(Vec<String>),
+        Theme,
+    }
+
+    struct TestScreen {
+        rows: u16,
+        cols: u16,
+        drawn: Vec<Drawn>,
+    }
+
+    impl TestScreen {
+        fn new(cols: u16, rows: u16) -> Self {
+            let mut screen = TestScreen {
+                rows: 0,
+                cols: 0,
+                drawn: vec![],
+            };
+            screen.resize(cols, rows);
+            screen
+        }
+
+        // The lines of the file last drawn, and the cursor among them.
+        fn content(&self) -> (Vec<&str>, usize) {
+            let content = self.drawn.iter().rev().find_map(|d| match d {
+                Drawn::Content { lines, cursor } => Some((lines, *cursor)),
+                _ => None,
+            });
+            let (lines, cursor) = content.expect("no content drawn");
+            (lines.iter().map(String::as_str).collect(), cursor)
+        }
+    }
+
+    impl Screen for TestScreen {
+        fn rows(&self) -> usize {
+            self.rows as usize
+        }
+
+        fn cols(&self) -> usize {
+            self.cols as usize
+        }
+
+        fn draw<'a>(
+            &'a mut self,
+            cmd: DrawCommand<'a>,
+        ) -> Result<(), DrawError> {
+            self.drawn.push(match cmd {
+                DrawCommand::DrawContent { lines, cursor, .. } => {
+                    let lines = lines.lines.iter().map(|l| l.to_string());
+                    Drawn::Content {
+                        lines: lines.collect(),
+                        cursor,
+                    }
+                }
+                DrawCommand::DrawStatus { status } => {
+                    Drawn::Status(status.into_owned())
+                }
+                DrawCommand::DrawOverlay { lines } => {
+                    Drawn::Overlay(lines.into_owned())
+                }
+                DrawCommand::SetTheme { .. } => Drawn::Theme,
+            });
+            Ok(())
+        }
+
+        fn resize(&mut self, cols: u16, rows: u16) {
+            self.cols = cols;
+            self.rows = layout(rows).0;
+        }
+
+        fn cleanup(&mut self) {}
+    }
+
+    fn numbered(lines: usize) -> String {
+        (0..lines).map(|i| format!("{}\n", i)).collect()
+    }
+
+    fn mode<'a>(
+        line_reader: &'a mut LineReader<'a>,
+        top: usize,
+        rows: usize,
+    ) -> NormalMode<'a> {
+        let (lines, _) = first_page(line_reader, false, top, rows);
+        let range = lines.range;
+        let mut mode = NormalMode::new(line_reader, Options::default());
+        mode.current_range = range;
+        mode
+    }
+
+    // Resizes the way the main loop does.
+    fn resize(mode: &mut NormalMode, screen: &mut TestScreen, rows: u16) {
+        screen.resize(80, rows);
+        draw(screen, mode.resize(screen.rows())).unwrap();
+    }
+
+    #[test]
+    fn resize_keeps_the_top_line() {
+        let text = numbered(100);
+        let mut reader = LineReader::new(text.as_bytes(), "test");
+        let mut screen = TestScreen::new(80, 11);
+        let mut mode = mode(&mut reader, 40, screen.rows());
+        mode.cursor = 3;
+        for rows in [6, 21, 11, 2] {
+            resize(&mut mode, &mut screen, rows);
+            let (lines, cursor) = screen.content();
+            assert_eq!(lines.len(), screen.rows());
+            assert_eq!(lines[0], "40", "{} rows", rows);
+            assert_eq!(cursor, 3.min(lines.len() - 1));
+        }
+    }
+
+    #[test]
+    fn resize_at_the_end_keeps_the_bottom_line() {
+        let text = numbered(100);
+        let mut reader = LineReader::new(text.as_bytes(), "test");
+        let mut screen = TestScreen::new(80, 11);
+        let mut mode = mode(&mut reader, 0, screen.rows());
+        let rows = screen.rows();
+        for vmove in [VerticalMove::Bottom, VerticalMove::LineUp] {
+            draw(&mut screen, mode.process_move(vmove, rows, 1, None)).unwrap();
+        }
+        // The cursor a line above the bottom one, on the line it stays on.
+        let (lines, cursor) = screen.content();
+        assert_eq!(lines.last(), Some(&"99"));
+        assert_eq!(lines[cursor], "98");
+        let below = rows - cursor;
+        for rows in [6, 21, 3, 11, 200] {
+            resize(&mut mode, &mut screen, rows);
+            let (lines, cursor) = screen.content();
+            assert_eq!(lines.len(), screen.rows().min(100));
+            assert_eq!(lines.last(), Some(&"99"), "{} rows", rows);
+            assert_eq!(lines.len() - cursor, below.min(lines.len()));
+        }
+    }
+
+    #[test]
+    fn bottom_of_a_big_file_stays_within_the_scan_budget() {
+        let text = numbered(100_000);
+        let mut reader = LineReader::new(text.as_bytes(), "test");
+        let mut screen = TestScreen::new(80, 11);
+        let rows = screen.rows();
+        let mut mode = mode(&mut reader, 0, rows);
+        mode.scan_budget = 10;
+        mode.options.line_numbers = LineNumbers::NonBlank;
+        mode.options.scrollbar = true;
+        mode.options.minimap = true;
+        let cmds = mode.process_move(VerticalMove::Bottom, rows, 1, None);
+        draw(&mut screen, cmds).unwrap();
+        let (lines, cursor) = screen.content();
+        assert_eq!(
+            lines,
+            [
+                "99990", "99991", "99992", "99993", "99994", "99995", "99996",
+                "99997", "99998", "99999"
+            ]
+        );
+        assert_eq!(cursor, rows - 1);
+        // Nor do commands that start from the current line, which wait for
+        // the count to finish while idle.
+        mode.pattern = Some("1".to_owned());
+        draw(&mut screen, mode.find_next(false, rows)).unwrap();
+        draw(
+            &mut screen,
+            mode.process_move(VerticalMove::ParagraphUp, rows, 1, None),
+        )
+        .unwrap();
+        assert!(mode.line_reader.indexed().is_none());
+        assert!(mode.indexing);
+        match screen.drawn.last() {
+            Some(Drawn::Status(status)) => {
+                assert!(status.contains("still indexing"), "{}", status)
+            }
+            drawn => panic!("{:?}", drawn),
+        }
+        while !mode.line_reader.index(mode.scan_budget) {}
+        draw(&mut screen, mode.find_next(true, rows)).unwrap();
+        assert_eq!(mode.current_line(), Some(99991));
+    }
+
+    #[test]
+    fn resize_redraws_only_the_file() {
+        let text = numbered(5);
+        let mut reader = LineReader::new(text.as_bytes(), "test");
+        let mut screen = TestScreen::new(80, 11);
+        let mut mode = mode(&mut reader, 0, screen.rows());
+        resize(&mut mode, &mut screen, 3);
+        let lines = vec!["0".to_owned(), "1".to_owned()];
+        let content = Drawn::Content { lines, cursor: 0 };
+        let status = Drawn::Status("test".to_owned());
+        assert_eq!(screen.drawn, [content, status]);
+    }
+
+    #[test]
+    fn help_pages_show_every_command() {
+        let help = keymap::help_lines();
+        for rows in [1, 5, help.len() - 1, help.len(), help.len() + 1] {
+            let mut screen = TestScreen::new(80, rows as u16 + 1);
+            let mut top = 0;
+            let mut shown = vec![];
+            while top < help.len() {
+                draw(&mut screen, help_page(&help, top, rows)).unwrap();
+                match &screen.drawn[screen.drawn.len() - 2..] {
+                    [Drawn::Overlay(lines), Drawn::Status(status)] => {
+                        assert!(lines.len() <= screen.rows());
+                        assert_eq!(
+                            status.contains("PageDown"),
+                            top + rows < help.len()
+                        );
+                        shown.extend(lines.iter().cloned());
+                    }
+                    drawn => panic!("{:?}", drawn),
+                }
+                top += rows;
+            }
+            assert_eq!(shown, help);
+        }
+    }
+
+    #[test]
+    fn palette_runs_the_selected_candidate() {
+        let mut action = PromptAction::Execute { selected: 0 };
+        let key = |code| KeyEvent::new(code, event::KeyModifiers::NONE);
+        let selected = |action: &PromptAction| match action {
+            PromptAction::Execute { selected } => *selected,
+            _ => unreachable!(),
+        };
+        assert!(select(&mut action, &key(KeyCode::Down)));
+        assert!(select(&mut action, &key(KeyCode::Tab)));
+        assert_eq!(selected(&action), 2);
+        assert!(select(&mut action, &key(KeyCode::BackTab)));
+        assert!(!select(&mut action, &key(KeyCode::Enter)));
+        assert_eq!(selected(&action), 1);
+        assert!(
+            palette("line numbers", 1) == Some(Command::ToggleRelativeNumbers)
+        );
+        // Typing starts over from the closest match.
+        assert!(!select(&mut action, &key(KeyCode::Char('s'))));
+        assert_eq!(selected(&action), 0);
+        assert!(select(&mut action, &key(KeyCode::Up)));
+        assert_eq!(selected(&action), 0);
+        assert!(palette("line numbers", 0) == Some(Command::ToggleLineNumbers));
+        // Past the end is the last candidate; ex commands aren't looked up.
+        let last = keymap::find("quit").last().map(|(cmd, _)| *cmd);
+        assert!(palette("quit", 100) == last);
+        assert!(palette("set number", 0).is_none());
+    }
 }
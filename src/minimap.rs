@@ -0,0 +1,61 @@
+use crate::line_reader::{LineReader, LinesRange};
+use crate::search::{self, Flags};
+
+// Columns taken at the right edge of the screen.
+pub const WIDTH: usize = 2;
+
+// The file is summed up in at most this many cells whatever the screen
+// height, so the map only needs building again when the search changes.
+const CELLS: usize = 256;
+
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+#[derive(Clone, Copy, Default)]
+pub struct Cell {
+    // 0 to 4, how long the lines are compared to the longest in the file.
+    pub density: usize,
+    pub matched: bool,
+}
+
+impl Cell {
+    pub fn shade(&self) -> char {
+        SHADES[self.density]
+    }
+
+    // Several cells shown in one row look like the busiest of them.
+    pub fn merge(cells: &[Cell]) -> Cell {
+        Cell {
+            density: cells.iter().map(|c| c.density).max().unwrap_or(0),
+            matched: cells.iter().any(|c| c.matched),
+        }
+    }
+}
+
+// Reads the whole file, so it wants the `total` lines counted first.
+pub fn build(
+    reader: &mut LineReader,
+    total: usize,
+    pattern: Option<&str>,
+    flags: Flags,
+) -> Vec<Cell> {
+    let n = total.min(CELLS);
+    let mut lengths = Vec::with_capacity(n);
+    let mut cells = vec![Cell::default(); n];
+    for (i, cell) in cells.iter_mut().enumerate() {
+        let range = i * total / n..(i + 1) * total / n;
+        let count = range.len().max(1);
+        let lines = reader.read(&LinesRange::pos(range));
+        let bytes = lines.buf_range.end - lines.buf_range.start.wrapping_add(1);
+        lengths.push(bytes / count);
+        cell.matched = pattern.is_some_and(|pattern| {
+            lines.lines.iter().any(|line| {
+                search::find_in_line(line, pattern, flags).is_some()
+            })
+        });
+    }
+    let longest = lengths.iter().max().copied().unwrap_or(0).max(1);
+    for (cell, len) in cells.iter_mut().zip(lengths) {
+        cell.density = (len * 4).div_ceil(longest);
+    }
+    cells
+}
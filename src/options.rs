@@ -0,0 +1,194 @@
+use crate::render::{Format, TAB_WIDTH};
+use crate::search::Flags;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    Relative,
+    // Numbers only lines that aren't empty, like `cat -b`.
+    NonBlank,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Theme {
+    Terminal,
+    Dark,
+    Light,
+}
+
+pub struct Options {
+    pub line_numbers: LineNumbers,
+    pub tabs: usize,
+    // Lines moved by j/k and the arrow keys.
+    pub scroll: usize,
+    // Columns moved by the Left and Right arrow keys. 0, the default, moves
+    // half the screen width, like less.
+    pub hscroll: usize,
+    // Stops horizontal moves on tab stops, for columns of tabular data.
+    pub hsnap: bool,
+    // Lines kept between the cursor and the edges of the screen.
+    pub scrolloff: usize,
+    // Screens to index ahead of the view in either direction.
+    pub readahead: usize,
+    pub raw: bool,
+    pub links: bool,
+    pub scrollbar: bool,
+    pub center: bool,
+    pub minimap: bool,
+    // Shows continuation lines, e.g. stack trace frames, as part of the
+    // line before them.
+    pub group: bool,
+    pub continuation: String,
+    pub ignorecase: bool,
+    pub whole_word: bool,
+    pub theme: Theme,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            line_numbers: LineNumbers::Off,
+            tabs: TAB_WIDTH,
+            scroll: 1,
+            hscroll: 0,
+            hsnap: false,
+            scrolloff: 0,
+            readahead: 1,
+            raw: false,
+            links: false,
+            scrollbar: false,
+            center: false,
+            minimap: false,
+            group: false,
+            continuation: String::new(),
+            ignorecase: false,
+            whole_word: false,
+            theme: Theme::Terminal,
+        }
+    }
+}
+
+impl Options {
+    pub fn format(&self) -> Format {
+        Format {
+            tabs: self.tabs,
+            raw: self.raw,
+            links: self.links,
+        }
+    }
+
+    // Lines starting with whitespace or the continuation prefix.
+    pub fn continues(&self, line: &str) -> bool {
+        line.starts_with(char::is_whitespace)
+            || !self.continuation.is_empty()
+                && line.starts_with(&self.continuation)
+    }
+
+    pub fn search_flags(&self) -> Flags {
+        Flags {
+            ignorecase: self.ignorecase,
+            whole_word: self.whole_word,
+            tabs: Some(self.tabs),
+        }
+    }
+
+    // Applies the arguments of `:set`, e.g. "tabs=4 nonumber ignorecase".
+    pub fn set(&mut self, args: &str) -> Result<(), String> {
+        args.split_whitespace()
+            .try_for_each(|arg| self.set_one(arg))
+    }
+
+    fn set_one(&mut self, arg: &str) -> Result<(), String> {
+        if let Some((name, value)) = arg.split_once('=') {
+            return match name {
+                "tabs" | "ts" => {
+                    self.tabs = value
+                        .parse()
+                        .ok()
+                        .filter(|&tabs| tabs > 0)
+                        .ok_or_else(|| format!("invalid value: {}", arg))?;
+                    Ok(())
+                }
+                "scroll" => {
+                    self.scroll = value
+                        .parse()
+                        .ok()
+                        .filter(|&scroll| scroll > 0)
+                        .ok_or_else(|| format!("invalid value: {}", arg))?;
+                    Ok(())
+                }
+                "hscroll" => {
+                    self.hscroll = value
+                        .parse()
+                        .map_err(|_| format!("invalid value: {}", arg))?;
+                    Ok(())
+                }
+                "scrolloff" | "so" => {
+                    self.scrolloff = value
+                        .parse()
+                        .map_err(|_| format!("invalid value: {}", arg))?;
+                    Ok(())
+                }
+                "readahead" => {
+                    self.readahead = value
+                        .parse()
+                        .map_err(|_| format!("invalid value: {}", arg))?;
+                    Ok(())
+                }
+                "continuation" => {
+                    self.continuation = value.to_owned();
+                    Ok(())
+                }
+                "background" | "bg" => {
+                    self.theme = match value {
+                        "dark" => Theme::Dark,
+                        "light" => Theme::Light,
+                        "terminal" => Theme::Terminal,
+                        _ => return Err(format!("invalid value: {}", arg)),
+                    };
+                    Ok(())
+                }
+                _ => Err(format!("unknown option: {}", name)),
+            };
+        }
+        let (name, on) = match arg.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (arg, true),
+        };
+        match name {
+            "number" | "nu" => {
+                self.line_numbers = match (on, self.line_numbers) {
+                    (false, _) => LineNumbers::Off,
+                    (true, LineNumbers::Off) => LineNumbers::Absolute,
+                    (true, numbers) => numbers,
+                }
+            }
+            "relativenumber" | "rnu" => {
+                self.line_numbers = match (on, self.line_numbers) {
+                    (true, _) => LineNumbers::Relative,
+                    (false, LineNumbers::Relative) => LineNumbers::Absolute,
+                    (false, numbers) => numbers,
+                }
+            }
+            "numbernonblank" | "nunb" => {
+                self.line_numbers = match (on, self.line_numbers) {
+                    (true, _) => LineNumbers::NonBlank,
+                    (false, LineNumbers::NonBlank) => LineNumbers::Absolute,
+                    (false, numbers) => numbers,
+                }
+            }
+            "ignorecase" | "ic" => self.ignorecase = on,
+            "wholeword" | "ww" => self.whole_word = on,
+            "raw" => self.raw = on,
+            "hsnap" => self.hsnap = on,
+            "hyperlinks" => self.links = on,
+            "scrollbar" => self.scrollbar = on,
+            "center" => self.center = on,
+            "minimap" => self.minimap = on,
+            "group" => self.group = on,
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use crossterm::style::Color;
+use std::env;
+
+// How many colors the terminal can show, from none to 24-bit.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Depth {
+    None,
+    Basic,
+    Ansi256,
+    TrueColor,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    Always,
+    Auto,
+    Never,
+}
+
+// A guess from the environment; there's no portable way to ask the terminal.
+pub fn detect(mode: ColorMode) -> Depth {
+    match mode {
+        ColorMode::Always => return Depth::TrueColor,
+        ColorMode::Never => return Depth::None,
+        ColorMode::Auto => {}
+    }
+    if env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        return Depth::None;
+    }
+    if let Ok("truecolor" | "24bit") = env::var("COLORTERM").as_deref() {
+        return Depth::TrueColor;
+    }
+    match env::var("TERM") {
+        Ok(term) if term.contains("256color") => Depth::Ansi256,
+        Ok(term) if term.is_empty() || term == "dumb" => Depth::None,
+        _ => Depth::Basic,
+    }
+}
+
+const BASIC: [Color; 16] = [
+    Color::Black,
+    Color::DarkRed,
+    Color::DarkGreen,
+    Color::DarkYellow,
+    Color::DarkBlue,
+    Color::DarkMagenta,
+    Color::DarkCyan,
+    Color::Grey,
+    Color::DarkGrey,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::White,
+];
+
+// The xterm default for the 6x6x6 cube and the gray ramp of the 256-color
+// palette, i.e. the entries past the 16 basic colors.
+fn ansi_rgb(n: u8) -> (u8, u8, u8) {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match n {
+        232..=255 => {
+            let v = 8 + 10 * (n - 232);
+            (v, v, v)
+        }
+        _ => {
+            let i = n.max(16) - 16;
+            let level = |c: u8| LEVELS[c as usize];
+            (level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+    }
+}
+
+fn to_ansi(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8| ((c as u16 * 5 + 127) / 255) as u8;
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+fn to_basic(r: u8, g: u8, b: u8) -> Color {
+    let max = r.max(g).max(b);
+    let on = |c: u8| c > max / 2 && c > 63;
+    let bits = on(r) as usize | (on(g) as usize) << 1 | (on(b) as usize) << 2;
+    let bright = if max > 191 { 8 } else { 0 };
+    match (bits, bright) {
+        (0, _) if max > 95 => Color::DarkGrey,
+        (7, 0) => Color::Grey,
+        (bits, bright) => BASIC[bits + bright],
+    }
+}
+
+// The nearest color the terminal can show; without colors that's the
+// terminal's own.
+pub fn fit(color: Color, depth: Depth) -> Color {
+    match (color, depth) {
+        (Color::Reset, _) => Color::Reset,
+        (_, Depth::None) => Color::Reset,
+        (Color::Rgb { r, g, b }, Depth::Ansi256) => {
+            Color::AnsiValue(to_ansi(r, g, b))
+        }
+        (Color::Rgb { r, g, b }, Depth::Basic) => to_basic(r, g, b),
+        (Color::AnsiValue(n), Depth::Basic) if n < 16 => BASIC[n as usize],
+        (Color::AnsiValue(n), Depth::Basic) => {
+            let (r, g, b) = ansi_rgb(n);
+            to_basic(r, g, b)
+        }
+        (color, _) => color,
+    }
+}
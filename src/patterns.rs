@@ -0,0 +1,33 @@
+use crossterm::style::Color;
+use std::convert::TryFrom;
+use std::fs;
+
+pub struct Highlight {
+    pub pattern: String,
+    pub color: Color,
+}
+
+// Reads `pattern=color` lines; blank lines and lines starting with '#' are
+// skipped. The color is whatever crossterm parses, e.g. "red", "dark_yellow".
+pub fn load(path: &str) -> Result<Vec<Highlight>, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut highlights = vec![];
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |what: &str| format!("{}:{}: {}", path, i + 1, what);
+        let (pattern, color) = line
+            .rsplit_once('=')
+            .filter(|(pattern, _)| !pattern.is_empty())
+            .ok_or_else(|| error("expected pattern=color"))?;
+        let color = Color::try_from(color.trim())
+            .map_err(|_| error(&format!("unknown color: {}", color)))?;
+        highlights.push(Highlight {
+            pattern: pattern.to_owned(),
+            color,
+        });
+    }
+    Ok(highlights)
+}
@@ -0,0 +1,126 @@
+use crossterm::event::KeyCode::{Backspace, Char, Enter, Esc};
+use crossterm::event::{KeyEvent, KeyModifiers};
+use std::borrow::Cow;
+
+use crate::DrawCommand;
+
+#[derive(Debug, PartialEq)]
+pub enum PromptResult {
+    Editing,
+    Submit(String),
+    Cancel,
+}
+
+pub struct PromptMode {
+    line: String,
+    prefix_len: usize,
+}
+
+impl PromptMode {
+    pub fn new(prefix: &str) -> Self {
+        PromptMode {
+            line: prefix.to_owned(),
+            prefix_len: prefix.len(),
+        }
+    }
+
+    pub fn input(&self) -> &str {
+        &self.line[self.prefix_len..]
+    }
+
+    pub fn process_key(&mut self, key: KeyEvent) -> PromptResult {
+        match key {
+            KeyEvent { code: Esc, .. } => PromptResult::Cancel,
+            KeyEvent {
+                code: Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => PromptResult::Cancel,
+            KeyEvent { code: Enter, .. } => {
+                PromptResult::Submit(self.input().to_owned())
+            }
+            KeyEvent {
+                code: Backspace, ..
+            } => {
+                if self.line.len() == self.prefix_len {
+                    return PromptResult::Cancel;
+                }
+                self.line.pop();
+                PromptResult::Editing
+            }
+            KeyEvent {
+                code: Char(c),
+                modifiers,
+                ..
+            } if !modifiers.contains(KeyModifiers::CONTROL) => {
+                self.line.push(c);
+                PromptResult::Editing
+            }
+            _ => PromptResult::Editing,
+        }
+    }
+
+    pub fn paste(&mut self, text: &str) -> PromptResult {
+        self.line.extend(text.chars().filter(|c| !c.is_control()));
+        PromptResult::Editing
+    }
+
+    pub fn mk_draw_commands(&self) -> Vec<DrawCommand<'_>> {
+        vec![DrawCommand::DrawStatus {
+            status: Cow::Borrowed(&self.line),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyCode;
+
+    fn press(prompt: &mut PromptMode, code: KeyCode) -> PromptResult {
+        prompt.process_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn typed(text: &str) -> PromptMode {
+        let mut prompt = PromptMode::new("/");
+        for c in text.chars() {
+            assert_eq!(press(&mut prompt, Char(c)), PromptResult::Editing);
+        }
+        prompt
+    }
+
+    #[test]
+    fn backspace_on_empty_prompt_cancels() {
+        let mut prompt = typed("");
+        assert_eq!(press(&mut prompt, Backspace), PromptResult::Cancel);
+    }
+
+    #[test]
+    fn backspace_keeps_the_prefix() {
+        let mut prompt = typed("ab");
+        assert_eq!(press(&mut prompt, Backspace), PromptResult::Editing);
+        assert_eq!(prompt.input(), "a");
+        assert_eq!(press(&mut prompt, Backspace), PromptResult::Editing);
+        assert_eq!(prompt.input(), "");
+        assert_eq!(press(&mut prompt, Backspace), PromptResult::Cancel);
+    }
+
+    #[test]
+    fn escape_cancels() {
+        let mut prompt = typed("abc");
+        assert_eq!(press(&mut prompt, Esc), PromptResult::Cancel);
+        let mut prompt = typed("abc");
+        let ctrl_c = KeyEvent::new(Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(prompt.process_key(ctrl_c), PromptResult::Cancel);
+    }
+
+    #[test]
+    fn enter_submits_input_without_prefix() {
+        let mut prompt = typed("héllo");
+        let submitted = PromptResult::Submit("héllo".to_owned());
+        assert_eq!(press(&mut prompt, Enter), submitted);
+        let mut prompt = typed("");
+        let submitted = PromptResult::Submit(String::new());
+        assert_eq!(press(&mut prompt, Enter), submitted);
+    }
+}
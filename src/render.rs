@@ -0,0 +1,500 @@
+use std::ops::Range;
+
+pub const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy)]
+pub struct Format {
+    pub tabs: usize,
+    pub raw: bool,
+    pub links: bool,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Mark {
+    Current,
+    Match,
+    Pattern(usize),
+    Link(String),
+}
+
+pub struct Rendered {
+    pub text: String,
+    pub marks: Vec<(Range<usize>, Mark)>,
+    pub cut_left: bool,
+    pub cut_right: bool,
+}
+
+fn caret(c: char) -> Option<String> {
+    match c as u32 {
+        0x00..=0x1f | 0x7f => Some(format!("^{}", (c as u8 ^ 0x40) as char)),
+        0x80..=0x9f => Some(format!("<{:02X}>", c as u32)),
+        _ => None,
+    }
+}
+
+// How a character at display column `col` is shown.
+fn repr(c: char, col: usize, format: Format) -> String {
+    match c {
+        '\t' => " ".repeat(format.tabs - col % format.tabs),
+        c => caret(c).unwrap_or_else(|| c.to_string()),
+    }
+}
+
+// Zero-width marks that combine with the previous character.
+const COMBINING: &[(u32, u32)] = &[
+    (0x0300, 0x036f),
+    (0x1ab0, 0x1aff),
+    (0x1dc0, 0x1dff),
+    (0x200b, 0x200f),
+    (0x20d0, 0x20ff),
+    (0xfe00, 0xfe0f),
+    (0xfe20, 0xfe2f),
+];
+
+// Characters that take two columns: the common East Asian wide and fullwidth
+// blocks and emoji. An approximation, but it covers CJK text and most emoji.
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115f),
+    (0x2e80, 0x303e),
+    (0x3041, 0x33ff),
+    (0x3400, 0x4dbf),
+    (0x4e00, 0x9fff),
+    (0xa000, 0xa4cf),
+    (0xac00, 0xd7a3),
+    (0xf900, 0xfaff),
+    (0xfe30, 0xfe4f),
+    (0xff00, 0xff60),
+    (0xffe0, 0xffe6),
+    (0x1f300, 0x1f64f),
+    (0x1f900, 0x1f9ff),
+    (0x20000, 0x2fffd),
+    (0x30000, 0x3fffd),
+];
+
+fn in_table(c: char, table: &[(u32, u32)]) -> bool {
+    let c = c as u32;
+    table
+        .iter()
+        .any(|&(first, last)| (first..=last).contains(&c))
+}
+
+pub fn char_width(c: char) -> usize {
+    if in_table(c, COMBINING) {
+        0
+    } else if in_table(c, WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+// The number of terminal columns the text takes. Escape sequences, which
+// only raw lines keep, take none.
+pub fn columns(text: &str) -> usize {
+    let mut n = 0;
+    let mut skip = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if i < skip => {}
+            '\x1b' => skip = i + escape_len(&text[i..]),
+            c => n += char_width(c),
+        }
+    }
+    n
+}
+
+// The length of the escape sequence `s` starts with: a CSI sequence, an OSC
+// string, or ESC and one more character. A CSI sequence without a final
+// byte is malformed and only its ESC is taken.
+fn escape_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    match bytes.get(1) {
+        Some(b'[') => {
+            let body = bytes[2..]
+                .iter()
+                .take_while(|b| (0x20..0x40).contains(*b))
+                .count();
+            match bytes.get(2 + body) {
+                Some(0x40..=0x7e) => 2 + body + 1,
+                _ => 1,
+            }
+        }
+        Some(b']') => {
+            let end = (2..s.len()).find(|&i| {
+                bytes[i] == 0x07
+                    || bytes[i] == 0x1b && bytes.get(i + 1) == Some(&b'\\')
+            });
+            match end {
+                Some(i) if bytes[i] == 0x07 => i + 1,
+                Some(i) => i + 2,
+                None => s.len(),
+            }
+        }
+        Some(_) => 1 + s[1..].chars().next().map_or(0, char::len_utf8),
+        None => 1,
+    }
+}
+
+// Colors and text attributes, the only escapes raw lines are allowed: the
+// others could move the cursor or clear the screen under the pager.
+fn is_sgr(seq: &str) -> bool {
+    seq.strip_prefix("\x1b[")
+        .and_then(|seq| seq.strip_suffix('m'))
+        .is_some_and(|params| {
+            params
+                .bytes()
+                .all(|b| b.is_ascii_digit() || b == b';' || b == b':')
+        })
+}
+
+// Walks the line only up to the end of the visible window, so the cost
+// doesn't depend on the length of the line.
+pub fn render(
+    line: &str,
+    offset: usize,
+    width: usize,
+    format: Format,
+    marks: &[(Range<usize>, Mark)],
+) -> Rendered {
+    let mut text = String::new();
+    let mut out_marks: Vec<(Range<usize>, Mark)> = vec![];
+    let end = offset + width;
+    let mut col = 0;
+    let mut cut_left = false;
+    let mut cut_right = false;
+    let mut skip = 0;
+    for (i, c) in line.char_indices() {
+        if i < skip {
+            continue;
+        }
+        if format.raw && c == '\x1b' {
+            let seq = &line[i..i + escape_len(&line[i..])];
+            skip = i + seq.len();
+            if is_sgr(seq) {
+                text.push_str(seq);
+            }
+            continue;
+        }
+        if col >= end {
+            cut_right = true;
+            break;
+        }
+        cut_left |= col < offset;
+        let repr = repr(c, col, format);
+        let repr_width = columns(&repr);
+        if col + repr_width > offset {
+            let start = text.len();
+            let mut c_col = col;
+            for c in repr.chars() {
+                let w = char_width(c);
+                if c_col >= offset && c_col + w <= end {
+                    text.push(c);
+                } else {
+                    // A wide character cut by the edge of the window shows
+                    // as blanks for its visible half.
+                    let visible = c_col.max(offset)..(c_col + w).min(end);
+                    text.push_str(&" ".repeat(visible.len()));
+                }
+                c_col += w;
+            }
+            let mark = marks.iter().find(|(m, _)| m.contains(&i));
+            if let Some((_, mark)) = mark {
+                match out_marks.last_mut() {
+                    Some((r, m)) if m == mark && r.end == start => {
+                        r.end = text.len()
+                    }
+                    _ => out_marks.push((start..text.len(), mark.clone())),
+                }
+            }
+        }
+        cut_right |= col + repr_width > end;
+        col += repr_width;
+    }
+    Rendered {
+        text,
+        marks: out_marks,
+        cut_left,
+        cut_right,
+    }
+}
+
+// The bytes of the line `render` walks over to show the window: the
+// characters at least partly in it, and any escapes between them.
+pub fn span(
+    line: &str,
+    offset: usize,
+    width: usize,
+    format: Format,
+) -> Range<usize> {
+    let end = offset + width;
+    let mut col = 0;
+    let mut start = None;
+    let mut skip = 0;
+    for (i, c) in line.char_indices() {
+        if i < skip {
+            continue;
+        }
+        if format.raw && c == '\x1b' {
+            skip = i + escape_len(&line[i..]);
+            continue;
+        }
+        if col >= end {
+            return start.unwrap_or(i)..i;
+        }
+        col += columns(&repr(c, col, format));
+        if start.is_none() && col > offset {
+            start = Some(i);
+        }
+    }
+    start.unwrap_or(line.len())..line.len()
+}
+
+fn tabs_only(tabs: usize) -> Format {
+    Format {
+        tabs,
+        raw: false,
+        links: false,
+    }
+}
+
+// The column the line reaches, the way `expand_tabs` counts them.
+pub fn column(line: &str, tabs: usize) -> usize {
+    line.chars()
+        .fold(0, |col, c| col + columns(&repr(c, col, tabs_only(tabs))))
+}
+
+// The line, starting at column `col`, with its tabs expanded to spaces the
+// way `render` shows them, and for every byte of the result the offset in
+// `line` it comes from.
+pub fn expand_tabs(
+    line: &str,
+    mut col: usize,
+    tabs: usize,
+) -> (String, Vec<usize>) {
+    let format = tabs_only(tabs);
+    let mut text = String::new();
+    let mut offsets = vec![];
+    for (i, c) in line.char_indices() {
+        let repr = repr(c, col, format);
+        col += columns(&repr);
+        match c {
+            '\t' => text.push_str(&repr),
+            c => text.push(c),
+        }
+        offsets.resize(text.len(), i);
+    }
+    (text, offsets)
+}
+
+// Finds http(s) URLs that overlap `span` of the line, leaving out trailing
+// punctuation that most likely belongs to the surrounding text. A URL has no
+// whitespace in it, so the line is looked at only as far as the words at
+// either end of the span.
+pub fn links(line: &str, span: Range<usize>) -> Vec<Range<usize>> {
+    let start = line[..span.start]
+        .trim_end_matches(|c: char| !c.is_whitespace())
+        .len();
+    let end = line[span.end..]
+        .find(char::is_whitespace)
+        .map_or(line.len(), |i| span.end + i);
+    let line = &line[..end];
+    let mut links = vec![];
+    let mut from = start;
+    while let Some(start) = line[from..].find("http").map(|i| from + i) {
+        let rest = &line[start..];
+        let end = rest
+            .find(|c: char| {
+                c.is_whitespace() || c.is_control() || "\"'<>`".contains(c)
+            })
+            .unwrap_or(rest.len());
+        let url = rest[..end].trim_end_matches(|c| ".,;:!?)]}".contains(c));
+        let scheme = ["http://", "https://"]
+            .iter()
+            .find(|scheme| url.starts_with(*scheme));
+        match scheme {
+            Some(scheme) if url.len() > scheme.len() => {
+                links.push(start..start + url.len());
+                from = start + url.len();
+            }
+            _ => from = start + "http".len(),
+        }
+    }
+    links.retain(|link| link.start < span.end && span.start < link.end);
+    links
+}
+
+// The text as an OSC 8 hyperlink to the url, ended with ST.
+pub fn hyperlink(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+pub fn ruler(offset: usize, width: usize) -> String {
+    (offset + 1..=offset + width)
+        .map(|col| match col {
+            col if col % 10 == 0 => ((col / 10 % 10) as u8 + b'0') as char,
+            col if col % 5 == 0 => '+',
+            _ => '.',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(raw: bool) -> Format {
+        Format {
+            tabs: 4,
+            raw,
+            links: false,
+        }
+    }
+
+    fn shown(line: &str, offset: usize, width: usize, raw: bool) -> String {
+        render(line, offset, width, format(raw), &[]).text
+    }
+
+    #[test]
+    fn raw_keeps_colors_only() {
+        let line = "a\x1b[31mred\x1b[0m \x1b[2Jb\x1b]0;title\x07c";
+        assert_eq!(shown(line, 0, 80, true), "a\x1b[31mred\x1b[0m bc");
+    }
+
+    #[test]
+    fn raw_drops_esc_of_malformed_csi() {
+        assert_eq!(shown("a\x1b[é b", 0, 80, true), "a[é b");
+        assert_eq!(shown("a\x1b[31", 0, 80, true), "a[31");
+        assert_eq!(columns("a\x1b[é b"), 5);
+    }
+
+    #[test]
+    fn raw_expands_tabs() {
+        assert_eq!(shown("a\tb", 0, 80, true), "a   b");
+        assert_eq!(shown("\x1b[1ma\tb", 0, 80, true), "\x1b[1ma   b");
+    }
+
+    #[test]
+    fn escapes_take_no_columns() {
+        let line = "\x1b[31mabcdef\x1b[0m";
+        let rendered = render(line, 0, 3, format(true), &[]);
+        assert_eq!(rendered.text, "\x1b[31mabc");
+        assert!(rendered.cut_right);
+        assert_eq!(columns(&rendered.text), 3);
+    }
+
+    #[test]
+    fn slices_multibyte_lines_by_column() {
+        let line = "héllo wörld";
+        for offset in 0..columns(line) {
+            let expected: String = line.chars().skip(offset).take(4).collect();
+            assert_eq!(shown(line, offset, 4, false), expected);
+        }
+        // Combining marks stay with the character before them.
+        assert_eq!(shown("e\u{301}xyz", 0, 2, false), "e\u{301}x");
+        assert_eq!(shown("e\u{301}xyz", 1, 2, false), "xy");
+    }
+
+    #[test]
+    fn wide_characters_cut_at_the_edges_show_as_blanks() {
+        let line = "日本語";
+        assert_eq!(shown(line, 0, 6, false), "日本語");
+        assert_eq!(shown(line, 2, 4, false), "本語");
+        // Cut at the left edge, at the right edge, and at both.
+        let rendered = render(line, 1, 5, format(false), &[]);
+        assert_eq!(rendered.text, " 本語");
+        assert!(rendered.cut_left && !rendered.cut_right);
+        let rendered = render(line, 0, 5, format(false), &[]);
+        assert_eq!(rendered.text, "日本 ");
+        assert!(!rendered.cut_left && rendered.cut_right);
+        let rendered = render(line, 1, 4, format(false), &[]);
+        assert_eq!(rendered.text, " 本 ");
+        assert!(rendered.cut_left && rendered.cut_right);
+        for offset in 0..6 {
+            for width in 1..6 {
+                let text = shown(line, offset, width, false);
+                assert_eq!(columns(&text), width.min(6 - offset));
+            }
+        }
+    }
+
+    #[test]
+    fn marks_on_wide_characters_cover_what_is_shown() {
+        let marks = [(3..6, Mark::Match)];
+        let rendered = render("日本語", 0, 6, format(false), &marks);
+        assert_eq!(rendered.text, "日本語");
+        assert!(rendered.marks == [(3..6, Mark::Match)]);
+        // Only the right half of 本 is visible: its mark covers the blank.
+        let rendered = render("日本語", 3, 3, format(false), &marks);
+        assert_eq!(rendered.text, " 語");
+        assert!(rendered.marks == [(0..1, Mark::Match)]);
+    }
+
+    #[test]
+    fn expand_tabs_maps_back_to_the_line() {
+        let (text, offsets) = expand_tabs("a\tb", 0, 4);
+        assert_eq!(text, "a   b");
+        assert_eq!(offsets, [0, 1, 1, 1, 2]);
+        let (text, offsets) = expand_tabs("\t", 0, 4);
+        assert_eq!(text, "    ");
+        assert_eq!(offsets, [0, 0, 0, 0]);
+        // Offsets are per byte, and columns count characters.
+        let (text, offsets) = expand_tabs("é\tx", 0, 4);
+        assert_eq!(text, "é   x");
+        assert_eq!(offsets, [0, 0, 2, 2, 2, 3]);
+        // The same tab stops as rendering.
+        for line in ["a\tb", "\t\tx", "abcd\te", "日\tx"] {
+            assert_eq!(expand_tabs(line, 0, 4).0, shown(line, 0, 80, false));
+        }
+        // Part way into a line, tab stops are where they'd be in all of it.
+        let (text, offsets) = expand_tabs("b\tc", 1, 4);
+        assert_eq!(text, "b  c");
+        assert_eq!(offsets, [0, 1, 1, 2]);
+        assert_eq!(column("a\tb", 4), 5);
+        assert_eq!(column("日\t^", 4), 5);
+    }
+
+    #[test]
+    fn hyperlinks_are_osc_8() {
+        let link = hyperlink("https://example.com/a?b=c", "example");
+        let expected = b"\x1b]8;;https://example.com/a?b=c\x1b\\\
+                         example\x1b]8;;\x1b\\";
+        assert_eq!(link.as_bytes(), expected);
+    }
+
+    #[test]
+    fn span_is_what_render_shows() {
+        assert_eq!(span("héllo wörld", 1, 4, format(false)), 1..6);
+        assert_eq!(span("héllo", 0, 80, format(false)), 0..6);
+        assert_eq!(span("héllo", 10, 4, format(false)), 6..6);
+        // Wide characters and tabs cut by the edges are in it.
+        assert_eq!(span("日本語", 1, 4, format(false)), 0..9);
+        assert_eq!(span("日本語", 2, 2, format(false)), 3..6);
+        assert_eq!(span("a\tb", 2, 1, format(false)), 1..2);
+        assert_eq!(span("\x1b[1mab\x1b[0mcd", 1, 2, format(true)), 5..11);
+        for offset in 0..8 {
+            for width in 0..8 {
+                let line = "x\té日\tz";
+                let range = span(line, offset, width, format(false));
+                let whole = render(line, offset, width, format(false), &[]);
+                let marks = [(range, Mark::Match)];
+                let marked = render(line, offset, width, format(false), &marks);
+                assert_eq!(marked.text, whole.text);
+                let all: usize =
+                    marked.marks.iter().map(|(r, _)| r.len()).sum();
+                assert_eq!(whole.text.len(), all);
+            }
+        }
+    }
+
+    #[test]
+    fn links_outside_the_span_are_not_looked_for() {
+        let line = "see https://a.example/x and http://b.example, https://";
+        let all = links(line, 0..line.len());
+        let urls: Vec<&str> = all.iter().map(|r| &line[r.clone()]).collect();
+        assert_eq!(urls, ["https://a.example/x", "http://b.example"]);
+        assert_eq!(links(line, 0..3), []);
+        assert_eq!(links(line, 10..12), [all[0].clone()]);
+        assert_eq!(links(line, 23..24), []);
+        assert_eq!(links(line, 20..40), all);
+    }
+}
@@ -0,0 +1,481 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::line_reader::{LineReader, LinesRange};
+use crate::render;
+
+const CHUNK: usize = 1024;
+
+pub type Match = (usize, Range<usize>);
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Flags {
+    pub ignorecase: bool,
+    pub whole_word: bool,
+    // The tab width when tabs are shown expanded.
+    pub tabs: Option<usize>,
+}
+
+// Only ASCII letters, digits and '_' count as word characters; Unicode word
+// boundaries are not handled yet.
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+// The word at or after byte `col`, used by `*` and `#`.
+pub fn word_at(line: &str, col: usize) -> Option<&str> {
+    let bytes = line.as_bytes();
+    let mut start = col + bytes.get(col..)?.iter().position(|&b| is_word(b))?;
+    while start > 0 && is_word(bytes[start - 1]) {
+        start -= 1;
+    }
+    let len = bytes[start..].iter().take_while(|&&b| is_word(b)).count();
+    Some(&line[start..start + len])
+}
+
+fn find_at(
+    line: &str,
+    pattern: &str,
+    whole_word: bool,
+    mut from: usize,
+) -> Option<Range<usize>> {
+    let bytes = line.as_bytes();
+    while let Some(start) = line[from..].find(pattern).map(|i| from + i) {
+        let end = start + pattern.len();
+        let bounded = (start == 0 || !is_word(bytes[start - 1]))
+            && (end == bytes.len() || !is_word(bytes[end]));
+        if !whole_word || bounded {
+            return Some(start..end);
+        }
+        from = start + line[start..].chars().next().map_or(1, char::len_utf8);
+        if from > line.len() {
+            return None;
+        }
+    }
+    None
+}
+
+// ASCII case folding keeps byte offsets valid for the original line.
+fn fold(s: &str, flags: Flags) -> Cow<'_, str> {
+    match flags.ignorecase {
+        true => Cow::Owned(s.to_ascii_lowercase()),
+        false => Cow::Borrowed(s),
+    }
+}
+
+// Searches the line as it's shown from column `col`, so spaces in the
+// pattern match what an expanded tab looks like. The offsets map the shown
+// text back to the line.
+fn shown<'l>(
+    line: &'l str,
+    col: usize,
+    pattern: &str,
+    flags: Flags,
+) -> (Cow<'l, str>, Option<Vec<usize>>) {
+    match flags.tabs {
+        Some(tabs) if line.contains('\t') && pattern.contains(' ') => {
+            let (text, offsets) = render::expand_tabs(line, col, tabs);
+            (Cow::Owned(text), Some(offsets))
+        }
+        _ => (Cow::Borrowed(line), None),
+    }
+}
+
+// A match in the shown text covers every character of the line it touches,
+// so part of a tab selects the whole tab.
+fn in_line(
+    m: Range<usize>,
+    offsets: &Option<Vec<usize>>,
+    line: &str,
+) -> Range<usize> {
+    let offsets = match offsets {
+        Some(offsets) => offsets,
+        None => return m,
+    };
+    let start = offsets.get(m.start).copied().unwrap_or(line.len());
+    if m.is_empty() {
+        return start..start;
+    }
+    let last = offsets[m.end - 1];
+    start..last + line[last..].chars().next().map_or(0, char::len_utf8)
+}
+
+pub fn find_in_line(
+    line: &str,
+    pattern: &str,
+    flags: Flags,
+) -> Option<Range<usize>> {
+    let (text, offsets) = shown(line, 0, pattern, flags);
+    let pattern = fold(pattern, flags);
+    let m = find_at(&fold(&text, flags), &pattern, flags.whole_word, 0)?;
+    Some(in_line(m, &offsets, line))
+}
+
+fn find_all(
+    line: &str,
+    col: usize,
+    pattern: &str,
+    flags: Flags,
+) -> Vec<Range<usize>> {
+    let (text, offsets) = shown(line, col, pattern, flags);
+    let (text, pattern) = (fold(&text, flags), fold(pattern, flags));
+    let mut found: Vec<Range<usize>> = vec![];
+    let mut from = 0;
+    while !pattern.is_empty() {
+        match find_at(&text, &pattern, flags.whole_word, from) {
+            Some(m) => {
+                from = m.end;
+                let m = in_line(m, &offsets, line);
+                match found.last_mut() {
+                    Some(last) if m.start < last.end => last.end = m.end,
+                    _ => found.push(m),
+                }
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+// The matches that overlap `span` of the line, looking at no more of it
+// than a match could reach from there. A match takes no more bytes of the
+// line than of the pattern, since an expanded tab only gets longer.
+pub fn find_all_in_span(
+    line: &str,
+    pattern: &str,
+    flags: Flags,
+    span: Range<usize>,
+) -> Vec<Range<usize>> {
+    // One byte more on either side, for telling where words end.
+    let reach = pattern.len() + 1;
+    let mut start = span.start.saturating_sub(reach);
+    while !line.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = line.len().min(span.end + reach);
+    while !line.is_char_boundary(end) {
+        end += 1;
+    }
+    let window = &line[start..end];
+    // Where the tab stops fall depends on all of the line before.
+    let col = match flags.tabs {
+        Some(tabs) if window.contains('\t') && pattern.contains(' ') => {
+            render::column(&line[..start], tabs)
+        }
+        _ => 0,
+    };
+    let mut found = find_all(window, col, pattern, flags);
+    found.retain(|m| start + m.start < span.end && span.start < start + m.end);
+    for m in &mut found {
+        *m = start + m.start..start + m.end;
+    }
+    found
+}
+
+pub fn find_forw(
+    reader: &mut LineReader,
+    pattern: &str,
+    flags: Flags,
+    from: usize,
+) -> Option<Match> {
+    scan_forw(reader, from, |line| find_in_line(line, pattern, flags))
+}
+
+pub fn find_back(
+    reader: &mut LineReader,
+    pattern: &str,
+    flags: Flags,
+    before: usize,
+) -> Option<Match> {
+    scan_back(reader, before, |line| find_in_line(line, pattern, flags))
+}
+
+pub fn scan_forw<F>(
+    reader: &mut LineReader,
+    from: usize,
+    mut f: F,
+) -> Option<Match>
+where
+    F: FnMut(&str) -> Option<Range<usize>>, {
+    let mut start = from;
+    loop {
+        let chunk = reader.read(&LinesRange::pos(start..start + CHUNK));
+        if chunk.range.range.start != start {
+            return None;
+        }
+        for (i, line) in chunk.lines.iter().enumerate() {
+            if let Some(m) = f(line) {
+                return Some((start + i, m));
+            }
+        }
+        if chunk.lines.len() < CHUNK {
+            return None;
+        }
+        start += CHUNK;
+    }
+}
+
+pub fn scan_back<F>(
+    reader: &mut LineReader,
+    before: usize,
+    mut f: F,
+) -> Option<Match>
+where
+    F: FnMut(&str) -> Option<Range<usize>>, {
+    let mut end = before;
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let chunk = reader.read(&LinesRange::pos(start..end));
+        for (i, line) in chunk.lines.iter().enumerate().rev() {
+            if let Some(m) = f(line) {
+                return Some((start + i, m));
+            }
+        }
+        end = start;
+    }
+    None
+}
+
+const BRACKETS: &[(u8, u8)] = &[(b'(', b')'), (b'[', b']'), (b'{', b'}')];
+
+fn balance<I>(
+    bytes: I,
+    this: u8,
+    other: u8,
+    depth: &mut usize,
+) -> Option<usize>
+where
+    I: Iterator<Item = (usize, u8)>, {
+    for (i, c) in bytes {
+        if c == this {
+            *depth += 1;
+        } else if c == other {
+            *depth -= 1;
+            if *depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+// Finds the bracket matching the first one at or after `col` on `line`,
+// counting nesting across lines in either direction.
+pub fn match_bracket(
+    reader: &mut LineReader,
+    line: usize,
+    col: usize,
+) -> Option<Match> {
+    let read = reader.read(&LinesRange::pos(line..line + 1));
+    let text = read.lines.into_iter().next()?;
+    let bytes = text.as_bytes();
+    let (at, this, other, forward) = (col..bytes.len()).find_map(|i| {
+        BRACKETS.iter().find_map(|&(open, close)| match bytes[i] {
+            c if c == open => Some((i, open, close, true)),
+            c if c == close => Some((i, close, open, false)),
+            _ => None,
+        })
+    })?;
+    let mut depth = 0;
+    let found = if forward {
+        let rest = bytes[at..].iter().copied().enumerate();
+        match balance(rest.map(|(i, c)| (at + i, c)), this, other, &mut depth) {
+            Some(i) => (line, i),
+            None => scan_forw(reader, line + 1, |l| {
+                let bytes = l.bytes().enumerate();
+                balance(bytes, this, other, &mut depth).map(|i| i..i + 1)
+            })
+            .map(|(line, m)| (line, m.start))?,
+        }
+    } else {
+        let rest = bytes[..=at].iter().copied().enumerate().rev();
+        match balance(rest, this, other, &mut depth) {
+            Some(i) => (line, i),
+            None => scan_back(reader, line, |l| {
+                let bytes = l.bytes().enumerate().rev();
+                balance(bytes, this, other, &mut depth).map(|i| i..i + 1)
+            })
+            .map(|(line, m)| (line, m.start))?,
+        }
+    };
+    Some((found.0, found.1..found.1 + 1))
+}
+
+// Remembers the match for every prefix typed so far: extending the pattern
+// resumes from the previous match, and backspacing reuses a stored result.
+pub struct IncSearch {
+    from: usize,
+    flags: Flags,
+    history: Vec<(String, Option<Match>)>,
+}
+
+impl IncSearch {
+    pub fn new(from: usize, flags: Flags) -> Self {
+        IncSearch {
+            from,
+            flags,
+            history: vec![],
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        reader: &mut LineReader,
+        pattern: &str,
+    ) -> Option<Match> {
+        while let Some((prev, _)) = self.history.last() {
+            if pattern.starts_with(prev.as_str()) {
+                break;
+            }
+            self.history.pop();
+        }
+        if pattern.is_empty() {
+            return None;
+        }
+        let found = match self.history.last() {
+            Some((prev, found)) if prev == pattern => return found.clone(),
+            // A whole word can match where its prefix didn't, even before
+            // it: "ab" is no word in "abc", but "abc" is.
+            _ if self.flags.whole_word => {
+                find_forw(reader, pattern, self.flags, self.from)
+            }
+            Some((_, None)) => None,
+            Some((_, Some((line, _)))) => {
+                find_forw(reader, pattern, self.flags, *line)
+            }
+            None => find_forw(reader, pattern, self.flags, self.from),
+        };
+        self.history.push((pattern.to_owned(), found.clone()));
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tabs(tabs: Option<usize>) -> Flags {
+        Flags {
+            tabs,
+            ..Flags::default()
+        }
+    }
+
+    #[test]
+    fn shown_expands_tabs_only_when_it_matters() {
+        let (text, offsets) = shown("a\tb", 0, "a  ", tabs(Some(4)));
+        assert_eq!(text, "a   b");
+        assert_eq!(offsets, Some(vec![0, 1, 1, 1, 2]));
+        // No tabs to expand, no spaces to match them, or tabs not expanded.
+        for (line, pattern, t) in [
+            ("a b", "a b", Some(4)),
+            ("a\tb", "a", Some(4)),
+            ("a\tb", "a b", None),
+        ] {
+            let (text, offsets) = shown(line, 0, pattern, tabs(t));
+            assert_eq!(text, line);
+            assert_eq!(offsets, None);
+        }
+    }
+
+    #[test]
+    fn in_line_covers_whole_tabs() {
+        let (_, offsets) = shown("a\tb", 0, " ", tabs(Some(4)));
+        assert_eq!(in_line(0..1, &offsets, "a\tb"), 0..1);
+        assert_eq!(in_line(0..2, &offsets, "a\tb"), 0..2);
+        assert_eq!(in_line(2..3, &offsets, "a\tb"), 1..2);
+        assert_eq!(in_line(3..5, &offsets, "a\tb"), 1..3);
+        assert_eq!(in_line(5..5, &offsets, "a\tb"), 3..3);
+        assert_eq!(in_line(2..4, &None, "a\tb"), 2..4);
+    }
+
+    #[test]
+    fn spaces_match_expanded_tabs() {
+        let flags = tabs(Some(4));
+        assert_eq!(find_in_line("a\tb", "a   b", flags), Some(0..3));
+        assert_eq!(find_in_line("a\tb", "a   b", tabs(None)), None);
+        // Part of a tab selects all of it.
+        assert_eq!(find_in_line("a\tb", "a ", flags), Some(0..2));
+        assert_eq!(find_in_line("a\tb", "  ", flags), Some(1..2));
+        assert_eq!(find_in_line("a\tb", " b", flags), Some(1..3));
+        assert_eq!(find_in_line("ab\tc", "b   c", flags), None);
+        let found = find_all("a\tb\tc", 0, " ", flags);
+        assert_eq!(found, [1..2, 3..4]);
+        // Overlapping matches merge.
+        let found = find_all("a \t b\tc", 0, "  ", flags);
+        assert_eq!(found, [1..4, 5..6]);
+    }
+
+    #[test]
+    fn matches_in_a_span_are_those_overlapping_it() {
+        let line = "ab\tabab a\tb äb\t\tab  a b";
+        for pattern in ["ab", "b a", "äb", "a", " ", "a  b", "b   ä"] {
+            for (whole_word, t) in
+                [(false, None), (true, None), (false, Some(4))]
+            {
+                let flags = Flags {
+                    whole_word,
+                    tabs: t,
+                    ..Flags::default()
+                };
+                let all = find_all(line, 0, pattern, flags);
+                for start in 0..line.len() {
+                    for end in start..=line.len() {
+                        if !line.is_char_boundary(start)
+                            || !line.is_char_boundary(end)
+                        {
+                            continue;
+                        }
+                        let mut expected = all.clone();
+                        expected.retain(|m| m.start < end && start < m.end);
+                        let found =
+                            find_all_in_span(line, pattern, flags, start..end);
+                        assert_eq!(found, expected, "{:?}", start..end);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_whole_word_search_rescans_from_the_origin() {
+        let text = "abc\nab\n";
+        let mut reader = LineReader::new(text.as_bytes(), "test");
+        let flags = Flags {
+            whole_word: true,
+            ..Flags::default()
+        };
+        let mut inc = IncSearch::new(0, flags);
+        assert_eq!(inc.update(&mut reader, "a"), None);
+        assert_eq!(inc.update(&mut reader, "ab"), Some((1, 0..2)));
+        let direct = find_forw(&mut reader, "abc", flags, 0);
+        assert_eq!(direct, Some((0, 0..3)));
+        assert_eq!(inc.update(&mut reader, "abc"), direct);
+        // And back again.
+        assert_eq!(inc.update(&mut reader, "ab"), Some((1, 0..2)));
+        assert_eq!(inc.update(&mut reader, "a"), None);
+    }
+
+    #[test]
+    fn incremental_search_narrows_from_the_last_match() {
+        let text = "x\nab\nabc\n";
+        let mut reader = LineReader::new(text.as_bytes(), "test");
+        let mut inc = IncSearch::new(0, Flags::default());
+        assert_eq!(inc.update(&mut reader, "a"), Some((1, 0..1)));
+        assert_eq!(inc.update(&mut reader, "abc"), Some((2, 0..3)));
+        assert_eq!(inc.update(&mut reader, "abcd"), None);
+        assert_eq!(inc.update(&mut reader, "ab"), Some((1, 0..2)));
+    }
+
+    // On what's shown, not the bytes: the second byte of "本" in Shift-JIS
+    // is a '{'.
+    #[test]
+    fn searches_decoded_text() {
+        let buf = b"\x93\xfa\x96{\x8c\xea\n{}";
+        let mut reader = LineReader::new(buf, "test");
+        reader.charset = encoding_rs::SHIFT_JIS;
+        let found = find_forw(&mut reader, "語", Flags::default(), 0);
+        assert_eq!(found, Some((0, 6..9)));
+        assert_eq!(match_bracket(&mut reader, 0, 0), None);
+    }
+}
@@ -0,0 +1,219 @@
+use memchr::{memchr, memchr_iter, memrchr};
+use std::str;
+
+// Reads the timestamp a line starts with as digits that sort in time order,
+// e.g. "2024-01-02T03:04:05" becomes "20240102030405". A shorter key sorts
+// before every longer one it's a prefix of, so "2024-01-02" can be typed to
+// mean the start of that day.
+pub type Parser = fn(&str) -> Option<String>;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+    "Nov", "Dec",
+];
+
+fn digits(s: &str, n: usize) -> Option<(&str, &str)> {
+    let d = s
+        .get(..n)
+        .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))?;
+    Some((d, &s[n..]))
+}
+
+// "hh[:mm[:ss[.fff]]]", appended to the key; the rest of the line is ignored.
+fn time(s: &str, key: &mut String) {
+    let (hours, mut s) = match digits(s, 2) {
+        Some(found) => found,
+        None => return,
+    };
+    key.push_str(hours);
+    for _ in 0..2 {
+        match s.strip_prefix(':').and_then(|s| digits(s, 2)) {
+            Some((field, rest)) => {
+                key.push_str(field);
+                s = rest;
+            }
+            None => return,
+        }
+    }
+    if let Some(fraction) = s.strip_prefix(|c| c == '.' || c == ',') {
+        key.extend(fraction.chars().take_while(char::is_ascii_digit));
+    }
+}
+
+// "2024-01-02", optionally followed by 'T' or a space and the time.
+pub fn iso8601(line: &str) -> Option<String> {
+    let s = line.strip_prefix('[').unwrap_or(line);
+    let (year, s) = digits(s, 4)?;
+    let (month, s) = digits(s.strip_prefix('-')?, 2)?;
+    let (day, s) = digits(s.strip_prefix('-')?, 2)?;
+    let mut key = [year, month, day].concat();
+    if let Some(s) = s.strip_prefix(|c| c == 'T' || c == ' ') {
+        time(s, &mut key);
+    }
+    Some(key)
+}
+
+// "Jan  2 03:04:05". Syslog leaves out the year, so a file spanning New
+// Year isn't sorted as far as this is concerned.
+pub fn syslog(line: &str) -> Option<String> {
+    let month = MONTHS.iter().position(|m| line.starts_with(m))?;
+    let s = line[3..].strip_prefix(' ')?.trim_start_matches(' ');
+    let len = s.bytes().take_while(u8::is_ascii_digit).count();
+    let day: u8 = s[..len].parse().ok().filter(|d| (1..=31).contains(d))?;
+    let mut key = format!("{:02}{:02}", month + 1, day);
+    if let Some(s) = s[len..].strip_prefix(' ') {
+        time(s, &mut key);
+    }
+    Some(key)
+}
+
+pub fn default(line: &str) -> Option<String> {
+    iso8601(line).or_else(|| syslog(line))
+}
+
+fn next_line(buf: &[u8], pos: usize) -> usize {
+    memchr(b'\n', &buf[pos..]).map_or(buf.len(), |i| pos + i + 1)
+}
+
+// The first line in pos..end with a timestamp, as its start and key.
+fn stamped(
+    buf: &[u8],
+    mut pos: usize,
+    end: usize,
+    parse: Parser,
+) -> Option<(usize, String)> {
+    while pos < end {
+        let next = next_line(buf, pos);
+        let line = str::from_utf8(&buf[pos..next]).unwrap_or("");
+        if let Some(key) = parse(line) {
+            return Some((pos, key));
+        }
+        pos = next;
+    }
+    None
+}
+
+// Finds the first line stamped at or after `target` by bisecting the bytes
+// of the file, which has to be sorted by time. Lines without a timestamp
+// are skipped over. Returns the line number.
+pub fn find(buf: &[u8], target: &str, parse: Parser) -> Option<usize> {
+    // Stamped lines before `lo` are earlier than the target, those from
+    // `hi` on are not.
+    let (mut lo, mut hi) = (0, buf.len());
+    while lo < hi {
+        let half = lo + (hi - lo) / 2;
+        let mid = memrchr(b'\n', &buf[lo..half]).map_or(lo, |i| lo + i + 1);
+        match stamped(buf, mid, hi, parse) {
+            Some((pos, key)) if key.as_str() < target => {
+                lo = next_line(buf, pos)
+            }
+            Some((pos, _)) => hi = pos,
+            None => hi = mid,
+        }
+    }
+    let (pos, _) = stamped(buf, lo, buf.len(), parse)?;
+    Some(memchr_iter(b'\n', &buf[..pos]).count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso8601_keys() {
+        let key = |line| iso8601(line);
+        assert_eq!(
+            key("2024-01-02T03:04:05.678Z x").unwrap(),
+            "20240102030405678"
+        );
+        assert_eq!(key("2024-01-02 03:04:05,5 x").unwrap(), "202401020304055");
+        assert_eq!(key("2024-01-02 03:04 x").unwrap(), "202401020304");
+        assert_eq!(key("[2024-01-02T03] x").unwrap(), "2024010203");
+        assert_eq!(key("2024-01-02").unwrap(), "20240102");
+        assert_eq!(key("2024-01-02x03:04").unwrap(), "20240102");
+        assert_eq!(key("2024-01-02 x").unwrap(), "20240102");
+        assert_eq!(key("2024-1-02"), None);
+        assert_eq!(key("24-01-02"), None);
+        assert_eq!(key(" 2024-01-02"), None);
+        assert_eq!(key(""), None);
+    }
+
+    #[test]
+    fn syslog_keys() {
+        let key = |line| syslog(line);
+        assert_eq!(key("Jan  2 03:04:05 host x").unwrap(), "0102030405");
+        assert_eq!(key("Dec 31 23:59:59 host x").unwrap(), "1231235959");
+        assert_eq!(key("Oct 15 09:30 x").unwrap(), "10150930");
+        assert_eq!(key("Feb 1").unwrap(), "0201");
+        assert_eq!(key("Jan 32 03:04:05"), None);
+        assert_eq!(key("Jan 0 03:04:05"), None);
+        assert_eq!(key("Jan03:04:05"), None);
+        assert_eq!(key("Foo  2 03:04:05"), None);
+        assert_eq!(key("Ja"), None);
+    }
+
+    #[test]
+    fn default_tries_iso8601_then_syslog() {
+        assert_eq!(default("2024-01-02 x").unwrap(), "20240102");
+        assert_eq!(default("Jan  2 x").unwrap(), "0102");
+        assert_eq!(default("x 2024-01-02"), None);
+    }
+
+    // The first line stamped at or after the target, the slow way.
+    fn scan(text: &str, target: &str) -> Option<usize> {
+        text.lines().position(|line| {
+            default(line).is_some_and(|key| key.as_str() >= target)
+        })
+    }
+
+    #[test]
+    fn find_on_edges() {
+        let text = "2024-01-01 a\n2024-01-02 b\n2024-01-03 c\n";
+        let find = |target| find(text.as_bytes(), target, default);
+        assert_eq!(find("2023"), Some(0));
+        assert_eq!(find("20240101"), Some(0));
+        assert_eq!(find("2024010112"), Some(1));
+        assert_eq!(find("20240103"), Some(2));
+        assert_eq!(find("20240104"), None);
+        assert_eq!(super::find(b"", "2024", default), None);
+        assert_eq!(super::find(b"a\nb\n", "2024", default), None);
+    }
+
+    #[test]
+    fn find_skips_lines_without_timestamps() {
+        let text =
+            "header\n\n2024-01-01 a\n  trace\n  trace\n2024-01-03 b\nend";
+        let find = |target| find(text.as_bytes(), target, default);
+        assert_eq!(find("2023"), Some(2));
+        assert_eq!(find("20240102"), Some(5));
+        assert_eq!(find("20240103"), Some(5));
+        assert_eq!(find("20240104"), None);
+    }
+
+    #[test]
+    fn find_agrees_with_a_scan() {
+        // Sorted stamps with repeats, with unstamped lines mixed in at
+        // places that vary with the seed.
+        let mut seed: u32 = 7;
+        for lines in 0..40 {
+            let mut text = String::new();
+            let mut day = 1;
+            for _ in 0..lines {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                match seed >> 16 & 3 {
+                    0 => text.push_str("  no stamp\n"),
+                    1 => text.push_str(&format!("2024-01-{:02} x\n", day)),
+                    _ => {
+                        day += 1;
+                        text.push_str(&format!("2024-01-{:02} y\n", day));
+                    }
+                }
+            }
+            for day in 0..=day + 1 {
+                let target = format!("202401{:02}", day);
+                let found = find(text.as_bytes(), &target, default);
+                assert_eq!(found, scan(&text, &target), "{:?} {}", text, day);
+            }
+        }
+    }
+}